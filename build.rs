@@ -53,23 +53,21 @@ fn main() {
         } else {
             (0.0, 0) // other oscs are silent
         };
-        if i == 3 {
-            // third osc cannot be modulated
-            oscillators.push(
-                PortList::oscillator_no_mod(amp, wave)
-                    .prefix(&format!("osc{i}_"), &format!("Osc {i} ")),
-            )
-        } else {
-            let modulator = format!("Osc {}", i + 1);
-            oscillators.push(
-                PortList::oscillator(amp, wave, &modulator)
-                    .prefix(&format!("osc{i}_"), &format!("Osc {i} ")),
-            )
-        }
+        // each osc is modulated by the one after it, with the third wrapping round to the first
+        let modulator = format!("Osc {}", i % 3 + 1);
+        oscillators.push(
+            PortList::oscillator(amp, wave, &modulator)
+                .prefix(&format!("osc{i}_"), &format!("Osc {i} ")),
+        )
     }
 
+    let modulation = PortList::modulation().prefix("mod_", "Mod. ");
+    let noise = PortList::noise().prefix("noise_", "Noise ");
+    let ring = PortList::ring().prefix("ring_", "Ring Mod ");
+    let harmonics = PortList::harmonics().prefix("harm_", "Harmonics ");
+
     // add oscillator ports
-    for control in oscillators.iter().flat_map(|x| &x.0) {
+    for control in oscillators.iter().flat_map(|x| &x.0).chain(modulation.0.iter()).chain(noise.0.iter()).chain(ring.0.iter()).chain(harmonics.0.iter()) {
         ttl.push_str(&ttl_control_divider);
         ttl.push_str(&control.to_ttl(port_index));
         port_index += 1;
@@ -90,10 +88,13 @@ fn main() {
     // prepare global controls
     let volume_envelope = PortList::envelope().prefix("vol_", "Volume Env. ");
     let lfo = PortList::lfo().prefix("lfo_", "Lfo 1 ");
+    let mseg = PortList::mseg().prefix("mseg_", "MSEG ");
+    let velocity = PortList::velocity().prefix("vel_", "Velocity ");
+    let key_scaling = PortList::key_scaling().prefix("key_", "Key Scaling ");
     let global_controls = PortList::global().prefix("", "Global ");
 
     // add global ports
-    for control in volume_envelope.0.iter().chain(lfo.0.iter()).chain(global_controls.0.iter()) {
+    for control in volume_envelope.0.iter().chain(lfo.0.iter()).chain(mseg.0.iter()).chain(velocity.0.iter()).chain(key_scaling.0.iter()).chain(global_controls.0.iter()) {
         ttl.push_str(&ttl_control_divider);
         ttl.push_str(&control.to_ttl(port_index));
         port_index += 1;
@@ -154,6 +155,9 @@ impl ControlPort {
     fn group(self, group: &str) -> Self {
         self.with_property(PortProperty::Group(group.to_string()))
     }
+    fn designation(self, designation: &str) -> Self {
+        self.with_property(PortProperty::Designation(designation.to_string()))
+    }
     fn to_ttl(&self, index: usize) -> String {
         let mut buf = String::with_capacity(2000);
         buf.push_str(&format!("                lv2:index {index} ;\n"));
@@ -204,6 +208,9 @@ impl ControlPort {
                 PortProperty::Group(group) => {
                     buf.push_str(&format!("\n                pg:group {group}"))
                 }
+                PortProperty::Designation(designation) => {
+                    buf.push_str(&format!("\n                lv2:designation {designation} ;"))
+                }
                 x => {
                     panic!("You need to add {:?} to the .to_ttl() function", x)
                 }
@@ -264,6 +271,8 @@ enum PortProperty {
     Logarithmic,
     Comment(String),
     Group(String),
+    /// Lets the host set the port, e.g. `time:beatsPerMinute` for the song tempo.
+    Designation(String),
 }
 
 use ControlRange::{Float, Int};
@@ -295,8 +304,43 @@ impl PortList {
                     "Saw".to_string(),
                     "Exponential".to_string(),
                     "Square".to_string(),
+                    "White Noise".to_string(),
+                    "Pink Noise".to_string(),
+                    "Brown Noise".to_string(),
+                    "Digital Noise".to_string(),
+                    "User Wavetable".to_string(),
+                    "Harmonic Editor".to_string(),
+                    "Pulse".to_string(),
                 ]),
-            ),
+            ).comment("Oscillator waveform. Noise ignores pitch, unison and modulation from other oscillators, but can still modulate them. Digital noise is clocked at the Noise Rate. User Wavetable plays the WAV file chosen by User Table. Harmonic Editor plays the spectrum made with the Harmonics controls. Pulse plays a pulse wave at the Pulse Width, and so does Square on Osc 3 for older patches."),
+            ControlPort::new(
+                "engine",
+                "Engine",
+                ControlRange::Enum(1, vec![
+                    "Naive".to_string(),
+                    "Wavetable".to_string(),
+                    "Additive".to_string(),
+                    "PolyBLEP".to_string(),
+                ]),
+            ).comment("How the oscillator generates its waves, all at matching volumes. Naive waves aren't bandlimited, so they alias at high pitches for a lo-fi sound. Wavetable is cheap and almost alias-free. Additive sums every harmonic each sample, so it's perfectly alias-free but very CPU heavy; best for offline renders. PolyBLEP smooths out the corners of naive waves, which is cheap and slightly brighter than the wavetables. The exponential wave, user wavetables and harmonic editor have no simple formula, so they play from wavetables in the other engines (without bandlimiting for Naive), apart from the exponential wave in Additive."),
+            ControlPort::new(
+                "user_table",
+                "User Table",
+                Int(1, (1, 128)),
+            ).comment("Which WAV file in the plugin's wavetables folder to play with the User Wavetable wave, counting alphabetically from 1. Saved patches remember the file by name, so adding files won't change what they play. Files can be single cycles, or Serum style wavetables made of 2048 sample frames. They're loaded when the plugin starts; if the file doesn't exist a sine is played instead."),
+            ControlPort::new(
+                "wt_position",
+                "WT Position",
+                Float(0.0, (0.0, 1.0)),
+            ).comment("Position in the user wavetable, sweeping from its first frame to its last. Can be modulated by the LFO and MSEG."),
+            ControlPort::new(
+                "wt_morph",
+                "WT Morph",
+                ControlRange::Enum(0, vec![
+                    "Crossfade".to_string(),
+                    "Spectral".to_string(),
+                ]),
+            ).comment("How the wavetable blends between frames. Crossfade mixes neighbouring frames, which can dip in volume when they're out of phase. Spectral blends each harmonic's volume and phase instead, so harmonics sweep smoothly from frame to frame."),
             ControlPort::new(
                 "amp",
                 "Amplitude",
@@ -317,82 +361,68 @@ impl PortList {
                 "Freq. Mult",
                 Int(0, (-64, 64)),
             ).comment("Oscillator pitch multiplier / divider. Positive values multiply pitch, while negative values divide. A value of 0 means this control is bypassed."),
+            ControlPort::new(
+                "pwm",
+                "Pulse Width",
+                Float(50.0, (0.0, 50.0)),
+            ).comment("Width of the Pulse wave (and Osc 3's Square), as a percentage of its cycle. Can be modulated by the filter envelope, the LFO and other oscillators. With the Wavetable and PolyBLEP engines the pulse is made with bandlimited steps, so it stays clean while the width moves."),
+            ControlPort::new(
+                "pwm_env",
+                "PWM Env.",
+                Float(0.0, (-50.0, 50.0)),
+            ).comment("How far the filter envelope moves the pulse width, as a percentage of the cycle."),
+            ControlPort::new(
+                "skew",
+                "Tri. Skew",
+                Float(0.0, (-100.0, 100.0)),
+            ).comment("Moves the peak of the triangle wave, bending it into a falling saw at -100 or a rising saw at 100. Like the pulse wave, the skewed triangle is made by subtracting two shifted waves, so it stays bandlimited."),
             ControlPort::new(
                 "pm",
                 &format!("<- {modulator} PM"),
                 Float(0.0, (0.0, 1.0)),
-            ).comment("Phase modulation of this oscillator by the oscillator after it."),
+            ).comment(&format!("Phase modulation of this oscillator by {modulator}, when the algorithm connects them.")),
             ControlPort::new(
                 "fm",
                 &format!("<- {modulator} FM"),
-                Float(0.0, (0.0, 1.0)),
-            ).comment("Frequency modulation of this oscillator by the oscillator after it. Can be used for vibrato effects when the modulator is at a low pitch."),
+                Float(0.0, (0.0, 10.0)),
+            ).comment(&format!("Frequency modulation of this oscillator by {modulator}, when the algorithm connects them. In Linear FM mode this is the modulation index: how far a sine modulator swings the frequency, relative to the modulator's own frequency. In Exponential mode it's the pitch swing in octaves instead. Can be used for vibrato effects when the modulator is at a low pitch.")),
             ControlPort::new(
                 "am",
                 &format!("<- {modulator} AM"),
                 Float(0.0, (0.0, 1.0)),
-            ).comment("Amplitude modulation of this oscillator by the oscillator after it. Also known as \'Ring Modulation\'. Can be used for telephone sounds and tremelo."),
+            ).comment(&format!("Unipolar amplitude modulation of this oscillator by {modulator}, when the algorithm connects them. The modulator only turns the volume down, so the original pitch stays in the sound. Can be used for telephone sounds and tremelo.")),
             ControlPort::new(
-                "voices",
-                "Unison",
-                Int(1, (1, 32)),
-            ).comment("Copies of this oscillator which are overlaid at different pitches. Useful for the supersaw sound."),
-            ControlPort::new(
-                "super_detune",
-                "Unison Detune",
-                Float(21.0, (0.0, 100.0)),
-            ).comment("Unison voice detune. Does nothing if unison = 1."),
+                "rm",
+                &format!("<- {modulator} RM"),
+                Float(0.0, (0.0, 1.0)),
+            ).comment(&format!("Ring modulation of this oscillator by {modulator}, when the algorithm connects them. At full amount the two are multiplied, which swaps the original pitch for the sum and difference of both frequencies. Good for bells, robot voices and clangorous metallic tones.")),
             ControlPort::new(
-                "phase",
-                "Phase",
-                Float(0.0, (0.0, 100.0)),
-            ).comment("Point at which the oscillator wave starts. Does nothing when Phase Rand = 100."),
+                "sync",
+                &format!("<- {modulator} Sync"),
+                ControlRange::Enum(0, vec![
+                    "Off".to_string(),
+                    "Hard".to_string(),
+                    "Soft".to_string(),
+                ]),
+            ).comment(&format!("Restarts this oscillator's cycle whenever {modulator} starts a new one, so this oscillator's pitch shapes the tone rather than the note. Hard sync jumps back to the start of the cycle for classic sync leads, while soft sync reverses direction for a smoother sound. Each unison voice syncs to the matching voice of the other oscillator. The Wavetable and PolyBLEP engines smooth the resets of sine, triangle, saw, square and pulse waves to stop aliasing.")),
             ControlPort::new(
-                "phase_rand",
-                "Phase Rand.",
-                Float(100.0, (0.0, 100.0)),
-            ).comment("Partially or fully randomises the point where the oscillator wave starts. Keep this fairly high when using Unison."),
-        ])
-    }
-    fn oscillator_no_mod(default_amp: f32, default_wave: usize) -> Self {
-        Self(vec![
+                "pwm_osc",
+                &format!("<- {modulator} PWM"),
+                Float(0.0, (0.0, 1.0)),
+            ).comment(&format!("Pulse width modulation of this oscillator by {modulator}.")),
             ControlPort::new(
-                "wave",
-                "Wave",
-                // Int(0, (0, 6)),
-                ControlRange::Enum(default_wave, vec![
-                    "Sine".to_string(),
-                    "Triangle".to_string(),
-                    "Saw".to_string(),
+                "fm_mode",
+                "FM Mode",
+                ControlRange::Enum(0, vec![
+                    "Linear".to_string(),
                     "Exponential".to_string(),
-                    "Square".to_string(),
                 ]),
-            ),
-            ControlPort::new(
-                "amp",
-                "Amplitude",
-                Float(default_amp, (0.0, 100.0)),
-            ).comment("Oscillator output volume. Doesn't affect PM, FM or AM."),
-            ControlPort::new(
-                "semitone",
-                "Detune",
-                Float(0.0, (-24.0, 24.0)),
-            ).comment("Oscillator detune in semitones."),
+            ).comment("How FM this oscillator receives changes its pitch. Linear FM adds to the frequency, and is through-zero: deep modulation runs the wave backwards instead of folding, so the pitch stays stable for bells and metallic tones. Exponential FM swings the pitch evenly up and down in octaves, which suits vibrato and sirens but pulls the pitch sharp when deep."),
             ControlPort::new(
-                "octave",
-                "Octave",
-                Int(0, (-8, 8)),
-            ).comment("Oscillator detune in octaves."),
-            ControlPort::new(
-                "multiplier",
-                "Freq. Mult",
-                Int(0, (-64, 64)),
-            ).comment("Oscillator pitch multiplier / divider. Positive values multiply pitch, while negative values divide. A value of 0 means this control is bypassed."),
-            ControlPort::new(
-                "pwm",
-                "Pulse Width",
-                Float(50.0, (0.0, 50.0)),
-            ).comment("Pulse width modulation. Adjusts the width of the square waveform."),
+                "feedback",
+                "Feedback",
+                Float(0.0, (0.0, 1.0)),
+            ).comment("Phase modulation of this oscillator by its own output, averaged over the last two samples to keep it from turning into noise. Works in every algorithm. With unison, every voice hears the sum of all of them, so detuned voices beat against each other through the feedback. Bends a sine into a saw-like tone without needing another oscillator, and becomes noisy towards the top of its range."),
             ControlPort::new(
                 "voices",
                 "Unison",
@@ -415,6 +445,55 @@ impl PortList {
             ).comment("Partially or fully randomises the point where the oscillator wave starts. Keep this fairly high when using Unison."),
         ])
     }
+    fn modulation() -> Self {
+        let mut ports = vec![
+            ControlPort::new(
+                "algorithm",
+                "Algorithm",
+                ControlRange::Enum(0, vec![
+                    "3 -> 2 -> 1".to_string(),
+                    "Parallel".to_string(),
+                    "3 -> 1 & 2 -> 1".to_string(),
+                    "2 + 3 -> 1".to_string(),
+                    "1 -> 2 -> 3".to_string(),
+                    "Cross".to_string(),
+                    "3 -> 2 -> 1 -> 3".to_string(),
+                ]),
+            ).comment("Which oscillators modulate which. 3 -> 2 -> 1 is a stack where each oscillator modulates the one before it. Parallel turns modulation off. 3 -> 1 & 2 -> 1 has Osc 2 and 3 each modulate Osc 1. 2 + 3 -> 1 is the stack, with Osc 3 also modulating Osc 1 directly. 1 -> 2 -> 3 is the stack upside down, ending on Osc 3. Cross has Osc 1 and 2 modulate each other. 3 -> 2 -> 1 -> 3 is a loop where each oscillator modulates the one before it, and Osc 1 modulates Osc 3. Each oscillator's Feedback applies in every algorithm. Connections to a higher numbered oscillator hear the previous sample. Each connection has its own PM, FM, AM and RM amounts: Osc 2 -> 1, Osc 3 -> 2 and Osc 1 -> 3 use the oscillators' own controls, and the others are below."),
+        ];
+        for (from, to) in [(3, 1), (1, 2), (2, 3)] {
+            let connection = format!("Osc {from}'s modulation of Osc {to}");
+            ports.push(
+                ControlPort::new(
+                    &format!("{from}_to_{to}_pm"),
+                    &format!("Osc {from} -> {to} PM"),
+                    Float(0.0, (0.0, 1.0)),
+                ).comment(&format!("Phase modulation amount of {connection}, when the algorithm uses it.")),
+            );
+            ports.push(
+                ControlPort::new(
+                    &format!("{from}_to_{to}_fm"),
+                    &format!("Osc {from} -> {to} FM"),
+                    Float(0.0, (0.0, 10.0)),
+                ).comment(&format!("Frequency modulation amount of {connection}, when the algorithm uses it. A modulation index in Linear FM mode, or octaves in Exponential mode.")),
+            );
+            ports.push(
+                ControlPort::new(
+                    &format!("{from}_to_{to}_am"),
+                    &format!("Osc {from} -> {to} AM"),
+                    Float(0.0, (0.0, 1.0)),
+                ).comment(&format!("Amplitude modulation amount of {connection}, when the algorithm uses it.")),
+            );
+            ports.push(
+                ControlPort::new(
+                    &format!("{from}_to_{to}_rm"),
+                    &format!("Osc {from} -> {to} RM"),
+                    Float(0.0, (0.0, 1.0)),
+                ).comment(&format!("Ring modulation amount of {connection}, when the algorithm uses it.")),
+            );
+        }
+        Self(ports)
+    }
     fn global() -> Self {
         Self(vec![
             ControlPort::new(
@@ -462,16 +541,66 @@ impl PortList {
                 "Bend Range",
                 Int(2, (-24, 24)),
             ).comment("Controls the range of the MIDI pitch wheel in semitones. Useful if you have a MIDI keyboard."),
+            ControlPort::new(
+                "smoothing_time",
+                "Smoothing",
+                Float(0.02, (0.001, 1.0)),
+            ).logarithmic()
+            .comment("Time taken for control changes (e.g. automation) to reach their new value, in seconds. Removes zipper noise and clicks from moving knobs. (Note: This control's minimum value (0.001) actually corresponds to 0 internally.)"),
+            ControlPort::new(
+                "smoothing_mode",
+                "Smoothing Curve",
+                ControlRange::Enum(0, vec![
+                    "Exponential".to_string(),
+                    "Linear".to_string(),
+                ]),
+            ).comment("Exponential smoothing moves quickly at first then settles gently into the new value. Linear smoothing moves at a constant speed and arrives exactly after the smoothing time."),
+            ControlPort::new(
+                "oversampling",
+                "Oversampling",
+                ControlRange::Enum(0, vec![
+                    "Off".to_string(),
+                    "2x".to_string(),
+                    "4x".to_string(),
+                    "8x".to_string(),
+                ]),
+            ).comment("Runs the oscillators faster than the sample rate, then filters them back down. Removes the aliasing from heavy FM / PM, feedback and sync, at the cost of CPU. The filtering delays the sound by 24 samples (half a millisecond at 48 kHz), so envelopes lead the oscillators by that much."),
+            ControlPort::new(
+                "oversampling_when",
+                "Oversample When",
+                ControlRange::Enum(0, vec![
+                    "Always".to_string(),
+                    "Offline Only".to_string(),
+                ]),
+            ).comment("Offline Only oversamples when the host is rendering faster than real time (e.g. exporting), so patches stay cheap to play live."),
+            ControlPort::new(
+                "freewheel",
+                "Offline Render",
+                Int(0, (0, 1)),
+            ).designation("lv2:freeWheeling")
+            .comment("Set by the host while rendering offline."),
         ])
     }
     fn envelope() -> Self {
         Self(vec![
+            ControlPort::new(
+                "delay",
+                "Delay",
+                Float(0.001, (0.001, 15.0)),
+            ).logarithmic()
+            .comment("Time before the envelope starts, in seconds. (Note: This control's minimum value (0.001) actually corresponds to 0 internally. This is a GUI hack to make logarithmic values display nicely in Ardour.)"),
             ControlPort::new(
                 "attack",
                 "Attack",
                 Float(0.002, (0.001, 15.0)),
             ).logarithmic()
             .comment("Envelope start time, in seconds. This gives a \\\"fade in\\\" effect when controlling volume. (Note: This control's minimum value (0.001) actually corresponds to 0 internally. This is a GUI hack to make logarithmic values display nicely in Ardour.)"),
+            ControlPort::new(
+                "hold",
+                "Hold",
+                Float(0.001, (0.001, 15.0)),
+            ).logarithmic()
+            .comment("Time the envelope stays at full level after the attack, before decaying, in seconds. (Note: This control's minimum value (0.001) actually corresponds to 0 internally. This is a GUI hack to make logarithmic values display nicely in Ardour.)"),
             ControlPort::new(
                 "decay",
                 "Decay",
@@ -483,6 +612,11 @@ impl PortList {
                 "Sustain",
                 Float(1.0, (0.0, 1.0)),
             ).comment("The level the envelope will remain at while the note is held. Used for sustained sounds, like flutes or strings. Has no effect when set to 0; the note will end when decay finishes."),
+            ControlPort::new(
+                "sustain_slope",
+                "Sustain Slope",
+                Float(0.0, (-1.0, 1.0)),
+            ).comment("How much the sustain level changes per second while the note is held. Negative values make held notes keep fading out, like a piano, while positive values make them swell back up."),
             ControlPort::new(
                 "release",
                 "Release",
@@ -490,25 +624,54 @@ impl PortList {
             ).logarithmic()
             .comment("Time for the envelope to finish after the note is released. Useful for sounds which persist a while after they're played, like bells or chimes. Has mostly no effect when sustain = 0."),
             ControlPort::new(
-                "slope",
-                "Slope",
+                "attack_curve",
+                "Attack Curve",
+                Float(0.25, (-8.0, 8.0)),
+            ).comment("Steepness of the attack. Positive values rise quickly at first, resulting in punchier sounds, while negative values start slowly. A curve of 0 is exactly linear."),
+            ControlPort::new(
+                "decay_curve",
+                "Decay Curve",
                 Float(1.0, (-8.0, 8.0)),
-            ).comment("Controls the steepness of the attack, decay and release slopes either exponentially or logarithmically. Positive slope means attack and decay will change logarithmically, resulting in punchier sounds, while negative slope will do the opposite. A slope of 0 results in exactly linear slopes."),
+            ).comment("Steepness of the decay. Positive values fall quickly at first, resulting in punchier sounds, while negative values start slowly. A curve of 0 is exactly linear."),
+            ControlPort::new(
+                "release_curve",
+                "Release Curve",
+                Float(1.0, (-8.0, 8.0)),
+            ).comment("Steepness of the release. Positive values fall quickly at first, while negative values start slowly. A curve of 0 is exactly linear."),
         ])
     }
     fn filter_envelope() -> Self {
         Self(vec![
+            // Was `env_amount`, a squared 0 to 1 amount. Renamed so hosts don't load old
+            // patches' values as octaves.
             ControlPort::new(
-                "env_amount",
+                "env_octaves",
                 "Amount",
-                Float(0.25, (0.0, 1.0)),
-            ).comment("Amount the envelope affects the filter cutoff."),
+                Float(3.0, (-10.0, 10.0)),
+            ).comment("Amount the envelope affects the filter cutoff, in octaves. At the envelope's peak the cutoff is shifted by this many octaves, so sweeps sound the same at any cutoff. Negative amounts sweep the cutoff downwards."),
+            ControlPort::new(
+                "env_velocity",
+                "Velocity",
+                Float(0.0, (0.0, 1.0)),
+            ).comment("How much note velocity scales the envelope amount. At 0 every note gets the full amount, at 1 the amount is multiplied by the note's velocity, so softer notes are swept less."),
+            ControlPort::new(
+                "delay",
+                "Delay",
+                Float(0.001, (0.001, 15.0)),
+            ).logarithmic()
+            .comment("Time before the envelope starts, in seconds. (Note: This control's minimum value (0.001) actually corresponds to 0 internally. This is a GUI hack to make logarithmic values display nicely in Ardour.)"),
             ControlPort::new(
                 "attack",
                 "Attack",
                 Float(0.001, (0.001, 15.0)),
             ).logarithmic()
             .comment("Envelope start time, in seconds. This gives a \\\"fade in\\\" effect when controlling volume. (Note: This control's minimum value (0.001) actually corresponds to 0 internally. This is a GUI hack to make logarithmic values display nicely in Ardour.)"),
+            ControlPort::new(
+                "hold",
+                "Hold",
+                Float(0.001, (0.001, 15.0)),
+            ).logarithmic()
+            .comment("Time the envelope stays at full level after the attack, before decaying, in seconds. (Note: This control's minimum value (0.001) actually corresponds to 0 internally. This is a GUI hack to make logarithmic values display nicely in Ardour.)"),
             ControlPort::new(
                 "decay",
                 "Decay",
@@ -520,6 +683,11 @@ impl PortList {
                 "Sustain",
                 Float(0.0, (0.0, 1.0)),
             ).comment("The level the envelope will remain at while the note is held. Used for sustained sounds, like flutes or strings. Has no effect when set to 0; the note will end when decay finishes."),
+            ControlPort::new(
+                "sustain_slope",
+                "Sustain Slope",
+                Float(0.0, (-1.0, 1.0)),
+            ).comment("How much the sustain level changes per second while the note is held. Negative values make held notes keep fading out, like a piano, while positive values make them swell back up."),
             ControlPort::new(
                 "release",
                 "Release",
@@ -527,10 +695,20 @@ impl PortList {
             ).logarithmic()
             .comment("Time for the envelope to finish after the note is released. Useful for sounds which persist a while after they're played, like bells or chimes. Has mostly no effect when sustain = 0."),
             ControlPort::new(
-                "slope",
-                "Slope",
+                "attack_curve",
+                "Attack Curve",
+                Float(0.25, (-8.0, 8.0)),
+            ).comment("Steepness of the attack. Positive values rise quickly at first, resulting in punchier sounds, while negative values start slowly. A curve of 0 is exactly linear."),
+            ControlPort::new(
+                "decay_curve",
+                "Decay Curve",
+                Float(1.0, (-8.0, 8.0)),
+            ).comment("Steepness of the decay. Positive values fall quickly at first, resulting in punchier sounds, while negative values start slowly. A curve of 0 is exactly linear."),
+            ControlPort::new(
+                "release_curve",
+                "Release Curve",
                 Float(1.0, (-8.0, 8.0)),
-            ).comment("Controls the steepness of the attack, decay and release slopes either exponentially or logarithmically. Positive slope means attack and decay will change logarithmically, resulting in punchier sounds, while negative slope will do the opposite. A slope of 0 results in exactly linear slopes."),
+            ).comment("Steepness of the release. Positive values fall quickly at first, while negative values start slowly. A curve of 0 is exactly linear."),
         ])
     }
     fn filter() -> Self {
@@ -544,8 +722,9 @@ impl PortList {
                     "RC".to_string(),
                     "Ladder".to_string(),
                     "Digital".to_string(),
+                    "RC (Soft)".to_string(),
                 ]),
-            ).comment("There are 3 filter models: Digital is an SVF identical to an IIR biquad filter (except stable over large cutoff changes), RC is a darker filter capable of aggressive self-resonance, and Ladder is based on a famous analog filter and sounds the best, with its code coming from janne808's Kocmoc Rack Modules project."),
+            ).comment("There are 4 filter models: Digital is an SVF identical to an IIR biquad filter (except stable over large cutoff changes), RC is a darker filter capable of aggressive self-resonance, RC (Soft) is the same filter saturating smoothly instead of clipping for a warmer self-resonance, and Ladder is based on a famous analog filter and sounds the best, with its code coming from janne808's Kocmoc Rack Modules project."),
             ControlPort::new(
                 "type",
                 "Type",
@@ -572,12 +751,40 @@ impl PortList {
                 "Drive",
                 Float(1.0, (0.01, 10.0)),
             ).logarithmic()
-            .comment("Multiplies the amplitude of the filter input, creating distortion inside the RC and Ladder filters. Does not amplify when Model = None or Digital, to keep volume equal between filters."),
+            .comment("Multiplies the amplitude of the signal going into the drive shape. With Drive Shape = Clean this only creates distortion inside the RC and Ladder filters, and does not amplify when Model = None or Digital, to keep volume equal between filters."),
+            ControlPort::new(
+                "drive_shape",
+                "Drive Shape",
+                ControlRange::Enum(0, vec![
+                    "Clean".to_string(),
+                    "Tanh".to_string(),
+                    "Soft Clip".to_string(),
+                    "Tube".to_string(),
+                    "Diode".to_string(),
+                    "Foldback".to_string(),
+                ]),
+            ).comment("Distortion curve applied by Drive, available for every filter model. Clean is plain gain. Tanh and Soft Clip are smooth symmetric saturation, Tube is asymmetric (adds even harmonics), Diode has a softer knee, and Foldback folds loud signals back on themselves for a metallic sound."),
+            ControlPort::new(
+                "drive_position",
+                "Drive Position",
+                ControlRange::Enum(0, vec![
+                    "Pre-Filter".to_string(),
+                    "Post-Filter".to_string(),
+                ]),
+            ).comment("Whether the drive shape distorts the signal before or after the filter. Pre-Filter lets the filter smooth out the distortion, Post-Filter distorts the filter's resonance too. Has no effect when Drive Shape = Clean."),
             ControlPort::new(
                 "keytrack",
                 "Keytrack",
                 Float(0.0, (0.0, 1.0)),
             ).comment("Amount the filter cutoff is affected by note frequency; Keytrack of 1.0 means the filter cutoff will follow the note frequency exactly, making higher notes brighter and lower notes darker."),
+            ControlPort::new(
+                "keytrack_mode",
+                "Keytrack Mode",
+                ControlRange::Enum(0, vec![
+                    "Relative".to_string(),
+                    "Calibrated".to_string(),
+                ]),
+            ).comment("Relative multiplies the cutoff by the note's distance from A4. Calibrated moves the cutoff towards the note's pitch instead, tuned for each filter model, so with Keytrack at 1.0 and high resonance the filter's self-oscillation can be played as a fourth oscillator."),
         ])
     }
     fn lfo() -> Self {
@@ -635,6 +842,287 @@ impl PortList {
                 Float(0.0, (0.0, 1.0)),
             ).logarithmic()
             .comment("LFO modulation of the filter's cutoff."),
+            ControlPort::new(
+                "position_mod",
+                "-> WT Position",
+                Float(0.0, (0.0, 1.0)),
+            ).logarithmic()
+            .comment("LFO modulation of the target's wavetable position. At 1 the LFO sweeps the whole table."),
+            ControlPort::new(
+                "pwm_mod",
+                "-> Pulse Width",
+                Float(0.0, (0.0, 1.0)),
+            ).logarithmic()
+            .comment("LFO modulation of the target's pulse width. At 1 the LFO sweeps the whole width range."),
+        ])
+    }
+    fn mseg() -> Self {
+        let mut ports = vec![
+            ControlPort::new(
+                "destination",
+                "Destination",
+                ControlRange::Enum(0, vec![
+                    "Off".to_string(),
+                    "All Osc. Pitch".to_string(),
+                    "Osc. 1 Pitch".to_string(),
+                    "Osc. 2 Pitch".to_string(),
+                    "Osc. 3 Pitch".to_string(),
+                    "Osc. 1 Amp.".to_string(),
+                    "Osc. 2 Amp.".to_string(),
+                    "Osc. 3 Amp.".to_string(),
+                    "Fil. Cutoff".to_string(),
+                    "Fil. Resonance".to_string(),
+                    "Volume".to_string(),
+                    "All Osc. WT Position".to_string(),
+                    "Osc. 1 WT Position".to_string(),
+                    "Osc. 2 WT Position".to_string(),
+                    "Osc. 3 WT Position".to_string(),
+                ]),
+            ).comment("Parameter the MSEG (multi-segment envelope) modulates. Pitch is shifted by up to 2 octaves, filter cutoff by up to 8 octaves, and resonance over its whole range. For amplitude and volume, an amount of 1 uses the MSEG's level as the gain. Wavetable position is swept over the whole table."),
+            ControlPort::new(
+                "amount",
+                "Amount",
+                Float(0.0, (-1.0, 1.0)),
+            ).comment("Depth of the MSEG's modulation. Negative amounts invert it."),
+            ControlPort::new(
+                "points",
+                "Points",
+                Int(4, (1, 6)),
+            ).comment("Number of points in use. The MSEG starts at 0 and moves to each point in turn, taking each point's time to get there, then holds the last point's level."),
+            ControlPort::new(
+                "loop",
+                "Loop",
+                ControlRange::Enum(0, vec![
+                    "Off".to_string(),
+                    "While Held".to_string(),
+                    "Always".to_string(),
+                ]),
+            ).comment("Repeats the points between Loop Start and Loop End. While Held stops looping when the note is released, Always keeps looping so the MSEG acts like an LFO."),
+            ControlPort::new(
+                "loop_start",
+                "Loop Start",
+                Int(1, (1, 6)),
+            ).comment("First point of the loop. The MSEG glides from Loop End to this point using this point's time and curve."),
+            ControlPort::new(
+                "loop_end",
+                "Loop End",
+                Int(2, (1, 6)),
+            ).comment("Last point of the loop. Looping is disabled when this is before Loop Start or after the last point."),
+            ControlPort::new(
+                "sustain",
+                "Sustain Point",
+                Int(0, (0, 6)),
+            ).comment("Point the MSEG stops at while the note is held. Releasing the note continues on to the points after it, which act as the release. 0 means the MSEG never waits."),
+            ControlPort::new(
+                "sync",
+                "Time Unit",
+                ControlRange::Enum(0, vec![
+                    "Seconds".to_string(),
+                    "Beats".to_string(),
+                ]),
+            ).comment("Whether point times are in seconds or in beats, following the host's tempo."),
+            ControlPort::new(
+                "bpm",
+                "Tempo",
+                Float(120.0, (1.0, 999.0)),
+            ).designation("time:beatsPerMinute")
+            .comment("Tempo used when Time Unit = Beats, in beats per minute. Set automatically by hosts which support it."),
+        ];
+        for point in 1..=6 {
+            ports.push(
+                ControlPort::new(
+                    &format!("p{point}_level"),
+                    &format!("Point {point} Level"),
+                    Float(if point == 1 { 1.0 } else { 0.0 }, (-1.0, 1.0)),
+                ),
+            );
+            ports.push(
+                ControlPort::new(
+                    &format!("p{point}_time"),
+                    &format!("Point {point} Time"),
+                    Float(0.25, (0.001, 16.0)),
+                ).logarithmic()
+                .comment("Time taken to reach this point from the previous one, in seconds or beats. (Note: This control's minimum value (0.001) actually corresponds to 0 internally. This is a GUI hack to make logarithmic values display nicely in Ardour.)"),
+            );
+            ports.push(
+                ControlPort::new(
+                    &format!("p{point}_curve"),
+                    &format!("Point {point} Curve"),
+                    Float(0.0, (-8.0, 8.0)),
+                ).comment("Steepness of the segment leading to this point. Positive values move quickly at first, negative values move slowly at first, and 0 is linear."),
+            );
+        }
+        Self(ports)
+    }
+    fn velocity() -> Self {
+        Self(vec![
+            ControlPort::new(
+                "amp_amount",
+                "Amp. Amount",
+                Float(1.0, (0.0, 1.0)),
+            ).comment("How much note velocity affects the output volume. At 0 every note plays at full volume."),
+            ControlPort::new(
+                "amp_curve",
+                "Amp. Curve",
+                ControlRange::Enum(0, vec![
+                    "Linear".to_string(),
+                    "Exponential".to_string(),
+                    "Logarithmic".to_string(),
+                    "Fixed".to_string(),
+                ]),
+            ).comment("Response of the output volume to velocity. Exponential makes soft notes much quieter, Logarithmic makes them only a little quieter, and Fixed ignores velocity."),
+            ControlPort::new(
+                "cutoff",
+                "Filter Cutoff",
+                Float(0.0, (-8.0, 8.0)),
+            ).comment("Octaves the filter cutoff is lowered by for the softest notes, so softer notes sound darker. The hardest notes use the cutoff as set. Negative values make soft notes brighter instead."),
+            ControlPort::new(
+                "attack",
+                "Env. Attack",
+                Float(0.0, (-1.0, 1.0)),
+            ).comment("How much velocity shortens the attack of both envelopes. At 1 the hardest notes attack almost instantly, while negative values make harder notes attack more slowly."),
+            ControlPort::new(
+                "osc1_amp",
+                "Osc 1 Amp.",
+                Float(0.0, (0.0, 1.0)),
+            ).comment("How much velocity scales Osc 1's level. Useful for bringing in a layer only on harder notes."),
+            ControlPort::new(
+                "osc2_amp",
+                "Osc 2 Amp.",
+                Float(0.0, (0.0, 1.0)),
+            ).comment("How much velocity scales Osc 2's level. Useful for bringing in a layer only on harder notes."),
+            ControlPort::new(
+                "osc3_amp",
+                "Osc 3 Amp.",
+                Float(0.0, (0.0, 1.0)),
+            ).comment("How much velocity scales Osc 3's level. Useful for bringing in a layer only on harder notes."),
+            ControlPort::new(
+                "osc1_mod",
+                "Osc 1 Mod.",
+                Float(0.0, (0.0, 1.0)),
+            ).comment("How much velocity scales the PM, FM and AM Osc 1 receives, so harder notes sound brighter or more metallic."),
+            ControlPort::new(
+                "osc2_mod",
+                "Osc 2 Mod.",
+                Float(0.0, (0.0, 1.0)),
+            ).comment("How much velocity scales the PM, FM and AM Osc 2 receives, so harder notes sound brighter or more metallic."),
+            ControlPort::new(
+                "osc3_mod",
+                "Osc 3 Mod.",
+                Float(0.0, (0.0, 1.0)),
+            ).comment("How much velocity scales the PM, FM and AM Osc 3 receives, so harder notes sound brighter or more metallic."),
+        ])
+    }
+    fn key_scaling() -> Self {
+        Self(vec![
+            ControlPort::new(
+                "breakpoint",
+                "Breakpoint",
+                Int(60, (0, 127)),
+            ).comment("MIDI note around which key scaling is applied. Notes at the breakpoint are unaffected. 60 is middle C."),
+            ControlPort::new(
+                "low_level",
+                "Low Level",
+                Float(0.0, (-24.0, 24.0)),
+            ).comment("Volume change per octave below the breakpoint, in dB. Negative values stop low notes from overwhelming the mix."),
+            ControlPort::new(
+                "high_level",
+                "High Level",
+                Float(0.0, (-24.0, 24.0)),
+            ).comment("Volume change per octave above the breakpoint, in dB. Negative values make high notes quieter."),
+            ControlPort::new(
+                "env_rate",
+                "Env. Rate",
+                Float(0.0, (-1.0, 1.0)),
+            ).comment("How much the envelopes speed up per octave above the breakpoint, and slow down below it. At 1, every envelope stage takes half as long one octave up, so high notes decay faster like a piano."),
+        ])
+    }
+    fn noise() -> Self {
+        Self(vec![
+            ControlPort::new(
+                "type",
+                "Type",
+                ControlRange::Enum(0, vec![
+                    "White".to_string(),
+                    "Pink".to_string(),
+                    "Brown".to_string(),
+                    "Digital".to_string(),
+                ]),
+            ).comment("Colour of the noise generator. Pink and brown noise have less treble, which suits breath and wind sounds. Digital noise comes from a shift register, like the noise channel on old sound chips."),
+            ControlPort::new(
+                "level",
+                "Level",
+                Float(0.0, (0.0, 100.0)),
+            ).comment("Noise generator volume. The noise goes through the filter along with the oscillators."),
+            ControlPort::new(
+                "rate",
+                "Rate",
+                Float(8000.0, (20.0, 48000.0)),
+            ).logarithmic()
+            .comment("Clock rate of digital noise in Hz, for both the noise generator and oscillators. Lower rates sound grainier and more metallic."),
         ])
     }
+    fn ring() -> Self {
+        Self(vec![
+            ControlPort::new(
+                "sources",
+                "Sources",
+                ControlRange::Enum(0, vec![
+                    "Osc 1 x 2".to_string(),
+                    "Osc 2 x 3".to_string(),
+                    "Osc 1 x 3".to_string(),
+                ]),
+            ).comment("Pair of oscillators multiplied by the ring modulator."),
+            ControlPort::new(
+                "level",
+                "Level",
+                Float(0.0, (0.0, 100.0)),
+            ).comment("Ring modulator volume, mixed in with the oscillators before the filter. The oscillators are multiplied before their own volumes are applied, so turn them down to hear only the ring modulator."),
+        ])
+    }
+    fn harmonics() -> Self {
+        let mut ports = vec![
+            ControlPort::new(
+                "rolloff",
+                "Rolloff",
+                Float(1.0, (0.0, 4.0)),
+            ).comment("How quickly the partials get quieter. Partial n has a volume of 1/n^rolloff, so 1 gives a saw wave, 2 is much softer, and 0 makes every partial equally loud."),
+            ControlPort::new(
+                "parity",
+                "Partials",
+                ControlRange::Enum(0, vec![
+                    "All".to_string(),
+                    "Odd".to_string(),
+                    "Even".to_string(),
+                ]),
+            ).comment("Which partials to keep. Odd partials give hollow, square-like sounds. Even keeps the fundamental so the pitch doesn't jump up an octave."),
+            ControlPort::new(
+                "every",
+                "Every Nth",
+                Int(1, (1, 16)),
+            ).comment("Only keeps every nth partial, counting from the fundamental. For example, 3 keeps partials 1, 4, 7, 10 and so on."),
+            ControlPort::new(
+                "count",
+                "Count",
+                Int(2048, (1, 2048)),
+            ).comment("Number of partials. Lower counts sound duller, like a lowpass filter which follows the pitch."),
+        ];
+        for i in 1..=16 {
+            ports.push(
+                ControlPort::new(
+                    &format!("p{i}_gain"),
+                    &format!("Partial {i} Gain"),
+                    Float(0.0, (-60.0, 24.0)),
+                ).comment("Volume of this partial relative to the generator controls above, in dB. -60 dB is close to silent."),
+            );
+            ports.push(
+                ControlPort::new(
+                    &format!("p{i}_phase"),
+                    &format!("Partial {i} Phase"),
+                    Float(0.0, (0.0, 1.0)),
+                ).comment("Phase of this partial, in cycles. Phase changes the shape of the wave without changing its tone, though it affects how it sounds when distorted or modulated."),
+            );
+        }
+        Self(ports)
+    }
 }
@@ -2,10 +2,23 @@ use std::f32::consts::PI;
 
 use lv2::prelude::*;
 
+mod state;
 mod synth;
+use state::{StateDescriptor, StateKeys, UserTables};
 use synth::{
-    filter::{FilterModel, FilterType},
-    oscillator::OscWave,
+    algorithm::{Algorithm, ModAmounts},
+    filter::{
+        drive::{DrivePosition, DriveShape},
+        FilterModel, FilterType, KeytrackMode,
+    },
+    harmonics::PartialParity,
+    mseg::{MsegDestination, MsegLoop, MSEG_POINTS},
+    noise::NoiseType,
+    oscillator::{FmMode, OscEngine, OscWave, SyncMode, WavetableMorph},
+    oversampling::Oversampling,
+    ring::RingSources,
+    smoothing::SmoothingMode,
+    velocity::VelocityCurve,
     Polyphony, ThreeOsc,
 };
 use wmidi::MidiMessage;
@@ -21,56 +34,162 @@ struct Ports {
     out_l: OutputPort<Audio>,
     out_r: OutputPort<Audio>,
     osc1_wave: InputPort<Control>,
+    osc1_engine: InputPort<Control>,
+    osc1_user_table: InputPort<Control>,
+    osc1_wt_position: InputPort<Control>,
+    osc1_wt_morph: InputPort<Control>,
     osc1_amp: InputPort<Control>,
     osc1_semitone: InputPort<Control>,
     osc1_octave: InputPort<Control>,
     osc1_multiplier: InputPort<Control>,
+    osc1_pwm: InputPort<Control>,
+    osc1_pwm_env: InputPort<Control>,
+    osc1_skew: InputPort<Control>,
     osc1_pm: InputPort<Control>,
     osc1_fm: InputPort<Control>,
     osc1_am: InputPort<Control>,
+    osc1_rm: InputPort<Control>,
+    osc1_sync: InputPort<Control>,
+    osc1_pwm_osc: InputPort<Control>,
+    osc1_fm_mode: InputPort<Control>,
+    osc1_feedback: InputPort<Control>,
     osc1_voices: InputPort<Control>,
     osc1_super_detune: InputPort<Control>,
     osc1_phase: InputPort<Control>,
     osc1_phase_rand: InputPort<Control>,
     osc2_wave: InputPort<Control>,
+    osc2_engine: InputPort<Control>,
+    osc2_user_table: InputPort<Control>,
+    osc2_wt_position: InputPort<Control>,
+    osc2_wt_morph: InputPort<Control>,
     osc2_amp: InputPort<Control>,
     osc2_semitone: InputPort<Control>,
     osc2_octave: InputPort<Control>,
     osc2_multiplier: InputPort<Control>,
+    osc2_pwm: InputPort<Control>,
+    osc2_pwm_env: InputPort<Control>,
+    osc2_skew: InputPort<Control>,
     osc2_pm: InputPort<Control>,
     osc2_fm: InputPort<Control>,
     osc2_am: InputPort<Control>,
+    osc2_rm: InputPort<Control>,
+    osc2_sync: InputPort<Control>,
+    osc2_pwm_osc: InputPort<Control>,
+    osc2_fm_mode: InputPort<Control>,
+    osc2_feedback: InputPort<Control>,
     osc2_voices: InputPort<Control>,
     osc2_super_detune: InputPort<Control>,
     osc2_phase: InputPort<Control>,
     osc2_phase_rand: InputPort<Control>,
     osc3_wave: InputPort<Control>,
+    osc3_engine: InputPort<Control>,
+    osc3_user_table: InputPort<Control>,
+    osc3_wt_position: InputPort<Control>,
+    osc3_wt_morph: InputPort<Control>,
     osc3_amp: InputPort<Control>,
     osc3_semitone: InputPort<Control>,
     osc3_octave: InputPort<Control>,
     osc3_multiplier: InputPort<Control>,
     osc3_pwm: InputPort<Control>,
+    osc3_pwm_env: InputPort<Control>,
+    osc3_skew: InputPort<Control>,
+    osc3_pm: InputPort<Control>,
+    osc3_fm: InputPort<Control>,
+    osc3_am: InputPort<Control>,
+    osc3_rm: InputPort<Control>,
+    osc3_sync: InputPort<Control>,
+    osc3_pwm_osc: InputPort<Control>,
+    osc3_fm_mode: InputPort<Control>,
+    osc3_feedback: InputPort<Control>,
     osc3_voices: InputPort<Control>,
     osc3_super_detune: InputPort<Control>,
     osc3_phase: InputPort<Control>,
     osc3_phase_rand: InputPort<Control>,
+    mod_algorithm: InputPort<Control>,
+    mod_3_to_1_pm: InputPort<Control>,
+    mod_3_to_1_fm: InputPort<Control>,
+    mod_3_to_1_am: InputPort<Control>,
+    mod_3_to_1_rm: InputPort<Control>,
+    mod_1_to_2_pm: InputPort<Control>,
+    mod_1_to_2_fm: InputPort<Control>,
+    mod_1_to_2_am: InputPort<Control>,
+    mod_1_to_2_rm: InputPort<Control>,
+    mod_2_to_3_pm: InputPort<Control>,
+    mod_2_to_3_fm: InputPort<Control>,
+    mod_2_to_3_am: InputPort<Control>,
+    mod_2_to_3_rm: InputPort<Control>,
+    noise_type: InputPort<Control>,
+    noise_level: InputPort<Control>,
+    noise_rate: InputPort<Control>,
+    ring_sources: InputPort<Control>,
+    ring_level: InputPort<Control>,
+    harm_rolloff: InputPort<Control>,
+    harm_parity: InputPort<Control>,
+    harm_every: InputPort<Control>,
+    harm_count: InputPort<Control>,
+    harm_p1_gain: InputPort<Control>,
+    harm_p1_phase: InputPort<Control>,
+    harm_p2_gain: InputPort<Control>,
+    harm_p2_phase: InputPort<Control>,
+    harm_p3_gain: InputPort<Control>,
+    harm_p3_phase: InputPort<Control>,
+    harm_p4_gain: InputPort<Control>,
+    harm_p4_phase: InputPort<Control>,
+    harm_p5_gain: InputPort<Control>,
+    harm_p5_phase: InputPort<Control>,
+    harm_p6_gain: InputPort<Control>,
+    harm_p6_phase: InputPort<Control>,
+    harm_p7_gain: InputPort<Control>,
+    harm_p7_phase: InputPort<Control>,
+    harm_p8_gain: InputPort<Control>,
+    harm_p8_phase: InputPort<Control>,
+    harm_p9_gain: InputPort<Control>,
+    harm_p9_phase: InputPort<Control>,
+    harm_p10_gain: InputPort<Control>,
+    harm_p10_phase: InputPort<Control>,
+    harm_p11_gain: InputPort<Control>,
+    harm_p11_phase: InputPort<Control>,
+    harm_p12_gain: InputPort<Control>,
+    harm_p12_phase: InputPort<Control>,
+    harm_p13_gain: InputPort<Control>,
+    harm_p13_phase: InputPort<Control>,
+    harm_p14_gain: InputPort<Control>,
+    harm_p14_phase: InputPort<Control>,
+    harm_p15_gain: InputPort<Control>,
+    harm_p15_phase: InputPort<Control>,
+    harm_p16_gain: InputPort<Control>,
+    harm_p16_phase: InputPort<Control>,
     fil1_model: InputPort<Control>,
     fil1_type: InputPort<Control>,
     fil1_cutoff: InputPort<Control>,
     fil1_resonance: InputPort<Control>,
     fil1_drive: InputPort<Control>,
+    fil1_drive_shape: InputPort<Control>,
+    fil1_drive_position: InputPort<Control>,
     fil1_keytrack: InputPort<Control>,
-    fil1_env_amount: InputPort<Control>,
+    fil1_keytrack_mode: InputPort<Control>,
+    fil1_env_octaves: InputPort<Control>,
+    fil1_env_velocity: InputPort<Control>,
+    fil1_delay: InputPort<Control>,
     fil1_attack: InputPort<Control>,
+    fil1_hold: InputPort<Control>,
     fil1_decay: InputPort<Control>,
     fil1_sustain: InputPort<Control>,
+    fil1_sustain_slope: InputPort<Control>,
     fil1_release: InputPort<Control>,
-    fil1_slope: InputPort<Control>,
+    fil1_attack_curve: InputPort<Control>,
+    fil1_decay_curve: InputPort<Control>,
+    fil1_release_curve: InputPort<Control>,
+    vol_delay: InputPort<Control>,
     vol_attack: InputPort<Control>,
+    vol_hold: InputPort<Control>,
     vol_decay: InputPort<Control>,
     vol_sustain: InputPort<Control>,
+    vol_sustain_slope: InputPort<Control>,
     vol_release: InputPort<Control>,
-    vol_slope: InputPort<Control>,
+    vol_attack_curve: InputPort<Control>,
+    vol_decay_curve: InputPort<Control>,
+    vol_release_curve: InputPort<Control>,
     lfo_target: InputPort<Control>,
     lfo_wave: InputPort<Control>,
     lfo_freq: InputPort<Control>,
@@ -78,13 +197,62 @@ struct Ports {
     lfo_amp_mod: InputPort<Control>,
     lfo_mod_mod: InputPort<Control>,
     lfo_filter_mod: InputPort<Control>,
+    lfo_position_mod: InputPort<Control>,
+    lfo_pwm_mod: InputPort<Control>,
+    mseg_destination: InputPort<Control>,
+    mseg_amount: InputPort<Control>,
+    mseg_points: InputPort<Control>,
+    mseg_loop: InputPort<Control>,
+    mseg_loop_start: InputPort<Control>,
+    mseg_loop_end: InputPort<Control>,
+    mseg_sustain: InputPort<Control>,
+    mseg_sync: InputPort<Control>,
+    mseg_bpm: InputPort<Control>,
+    mseg_p1_level: InputPort<Control>,
+    mseg_p1_time: InputPort<Control>,
+    mseg_p1_curve: InputPort<Control>,
+    mseg_p2_level: InputPort<Control>,
+    mseg_p2_time: InputPort<Control>,
+    mseg_p2_curve: InputPort<Control>,
+    mseg_p3_level: InputPort<Control>,
+    mseg_p3_time: InputPort<Control>,
+    mseg_p3_curve: InputPort<Control>,
+    mseg_p4_level: InputPort<Control>,
+    mseg_p4_time: InputPort<Control>,
+    mseg_p4_curve: InputPort<Control>,
+    mseg_p5_level: InputPort<Control>,
+    mseg_p5_time: InputPort<Control>,
+    mseg_p5_curve: InputPort<Control>,
+    mseg_p6_level: InputPort<Control>,
+    mseg_p6_time: InputPort<Control>,
+    mseg_p6_curve: InputPort<Control>,
+    vel_amp_amount: InputPort<Control>,
+    vel_amp_curve: InputPort<Control>,
+    vel_cutoff: InputPort<Control>,
+    vel_attack: InputPort<Control>,
+    vel_osc1_amp: InputPort<Control>,
+    vel_osc2_amp: InputPort<Control>,
+    vel_osc3_amp: InputPort<Control>,
+    vel_osc1_mod: InputPort<Control>,
+    vel_osc2_mod: InputPort<Control>,
+    vel_osc3_mod: InputPort<Control>,
+    key_breakpoint: InputPort<Control>,
+    key_low_level: InputPort<Control>,
+    key_high_level: InputPort<Control>,
+    key_env_rate: InputPort<Control>,
     polyphony: InputPort<Control>,
     portamento_rate: InputPort<Control>,
     pitch_offset: InputPort<Control>,
     octave_detune: InputPort<Control>,
     output_gain: InputPort<Control>,
+    stereo_width: InputPort<Control>,
     global_pitch: InputPort<Control>,
     bend_range: InputPort<Control>,
+    smoothing_time: InputPort<Control>,
+    smoothing_mode: InputPort<Control>,
+    oversampling: InputPort<Control>,
+    oversampling_when: InputPort<Control>,
+    freewheel: InputPort<Control>,
 }
 
 #[derive(FeatureCollection)]
@@ -103,6 +271,7 @@ pub struct URIDs {
 struct SynthLv2 {
     synth: ThreeOsc,
     urids: URIDs,
+    user_tables: UserTables,
 }
 
 impl Plugin for SynthLv2 {
@@ -114,9 +283,17 @@ impl Plugin for SynthLv2 {
     /// Create the plugin. Does initial setup (i.e. necessary allocation to stay realtime safe)
     fn new(plugin_info: &PluginInfo, features: &mut Features<'static>) -> Option<Self> {
         println!("Sample rate was: {}", plugin_info.sample_rate());
+        let mut synth = ThreeOsc::new(plugin_info.sample_rate());
+        // User wavetables live in the bundle. Oscillators pick one with a normal control port,
+        // and the plugin state remembers it by file name.
+        synth.load_user_wavetables(&plugin_info.bundle_path().join("wavetables"));
+        let urids: URIDs = features.map.populate_collection()?;
+        let keys = StateKeys::map(&features.map, urids.atom.string.get(), urids.atom.float.get())?;
+        let names = synth.waves.user_tables.iter().map(|x| x.name.clone()).collect();
         Some(Self {
-            synth: ThreeOsc::new(plugin_info.sample_rate()),
-            urids: features.map.populate_collection()?,
+            synth,
+            urids,
+            user_tables: UserTables::new(names, keys),
         })
     }
 
@@ -129,7 +306,25 @@ impl Plugin for SynthLv2 {
             0.0
         };
         self.synth.output_volume = coef;
+        self.synth.smoothing.time = if *ports.smoothing_time <= 0.001 {
+            0.0
+        } else {
+            *ports.smoothing_time
+        };
+        self.synth.smoothing.mode = match *ports.smoothing_mode {
+            x if x < 1.0 => SmoothingMode::OnePole,
+            _ => SmoothingMode::Linear,
+        };
+        let offline_only = *ports.oversampling_when >= 1.0;
+        self.synth.oversampling = if offline_only && *ports.freewheel < 0.5 {
+            Oversampling::Off
+        } else {
+            Oversampling::from_index(*ports.oversampling)
+        };
         self.synth.bend_range = *ports.bend_range;
+        // Stereo unison is unfinished (the right channel bypasses the filter), so this port
+        // is ignored until it works.
+        let _ = ports.stereo_width;
         self.synth.polyphony = match *ports.polyphony {
             x if x < 1.0 => Polyphony::Polyphonic,
             x if x < 2.0 => Polyphony::Monophonic,
@@ -146,46 +341,47 @@ impl Plugin for SynthLv2 {
 
         // adjust master gain envelope
 
-        // Attack and decay's minimum value of 0.001 is manually set to 0.0. This is a workaround to
-        // make logarithmic values display nicely in Ardour (which ignores the 'logarithmic' port
-        // property when the port's minimum value is 0) while still allowing instant attack times.
-        // Naturally we don't do this for the release port.
+        // Every stage except release treats its minimum value of 0.001 as 0.0 (see
+        // `zero_at_minimum`). Naturally we don't do this for the release port.
         // TODO: decide if the decay control needs this at all
-        self.synth.gain_envelope.attack_time = if *ports.vol_attack <= 0.001 {
-            0.0
-        } else {
-            *ports.vol_attack
-        };
-        self.synth.gain_envelope.decay_time = if *ports.vol_decay <= 0.001 {
-            0.0
-        } else {
-            *ports.vol_decay
-        };
-        self.synth.gain_envelope.sustain_level = *ports.vol_sustain;
-        self.synth.gain_envelope.release_time = *ports.vol_release;
-        self.synth.gain_envelope.set_slope(*ports.vol_slope);
+        let envelope = &mut self.synth.gain_envelope;
+        envelope.delay_time = zero_at_minimum(*ports.vol_delay);
+        envelope.attack_time = zero_at_minimum(*ports.vol_attack);
+        envelope.hold_time = zero_at_minimum(*ports.vol_hold);
+        envelope.decay_time = zero_at_minimum(*ports.vol_decay);
+        envelope.sustain_level = *ports.vol_sustain;
+        envelope.sustain_slope = *ports.vol_sustain_slope;
+        envelope.release_time = *ports.vol_release;
+        envelope.set_attack_curve(*ports.vol_attack_curve);
+        envelope.set_decay_curve(*ports.vol_decay_curve);
+        envelope.set_release_curve(*ports.vol_release_curve);
 
-        self.synth.filter_controller.envelope_amount = (*ports.fil1_env_amount).powi(2) * 1.0;
+        self.synth.filter_controller.envelope_amount = *ports.fil1_env_octaves;
+        self.synth.filter_controller.envelope_velocity = *ports.fil1_env_velocity;
         self.synth.filter_controller.keytrack = *ports.fil1_keytrack;
-        self.synth.filter_controller.cutoff_envelope.attack_time = if *ports.fil1_attack <= 0.001 {
-            0.0
-        } else {
-            *ports.fil1_attack
-        };
-        self.synth.filter_controller.cutoff_envelope.decay_time = if *ports.fil1_decay <= 0.001 {
-            0.0
-        } else {
-            *ports.fil1_decay
+        self.synth.filter_controller.keytrack_mode = match *ports.fil1_keytrack_mode {
+            x if x < 1.0 => KeytrackMode::Relative,
+            _ => KeytrackMode::Calibrated,
         };
-        self.synth.filter_controller.cutoff_envelope.sustain_level = *ports.fil1_sustain;
-        self.synth.filter_controller.cutoff_envelope.release_time = *ports.fil1_release;
-        self.synth
-            .filter_controller
-            .cutoff_envelope
-            .set_slope(*ports.fil1_slope);
+        let envelope = &mut self.synth.filter_controller.cutoff_envelope;
+        envelope.delay_time = zero_at_minimum(*ports.fil1_delay);
+        envelope.attack_time = zero_at_minimum(*ports.fil1_attack);
+        envelope.hold_time = zero_at_minimum(*ports.fil1_hold);
+        envelope.decay_time = zero_at_minimum(*ports.fil1_decay);
+        envelope.sustain_level = *ports.fil1_sustain;
+        envelope.sustain_slope = *ports.fil1_sustain_slope;
+        envelope.release_time = *ports.fil1_release;
+        envelope.set_attack_curve(*ports.fil1_attack_curve);
+        envelope.set_decay_curve(*ports.fil1_decay_curve);
+        envelope.set_release_curve(*ports.fil1_release_curve);
         self.synth.filter_controller.target_cutoff = *ports.fil1_cutoff;
         self.synth.filter_controller.resonance = *ports.fil1_resonance;
         self.synth.filter_controller.drive = *ports.fil1_drive;
+        self.synth.filter_controller.drive_shape = DriveShape::from_index(*ports.fil1_drive_shape);
+        self.synth.filter_controller.drive_position = match *ports.fil1_drive_position {
+            x if x < 1.0 => DrivePosition::PreFilter,
+            _ => DrivePosition::PostFilter,
+        };
         self.synth.filter_controller.filter_type = match *ports.fil1_type {
             x if x < 1.0 => FilterType::Lowpass,
             x if x < 2.0 => FilterType::Bandpass,
@@ -196,7 +392,8 @@ impl Plugin for SynthLv2 {
             x if x < 1.0 => FilterModel::None,
             x if x < 2.0 => FilterModel::RcFilter,
             x if x < 3.0 => FilterModel::LadderFilter,
-            x if x <= 4.0 => FilterModel::SvfSimperFilter,
+            x if x < 4.0 => FilterModel::SvfSimperFilter,
+            x if x < 5.0 => FilterModel::RcFilterSoft,
             _ => FilterModel::None,
         };
 
@@ -214,6 +411,75 @@ impl Plugin for SynthLv2 {
         self.synth.lfo_params.amp_mod = *ports.lfo_amp_mod;
         self.synth.lfo_params.mod_mod = *ports.lfo_mod_mod;
         self.synth.lfo_params.filter_mod = *ports.lfo_filter_mod;
+        self.synth.lfo_params.position_mod = *ports.lfo_position_mod;
+        self.synth.lfo_params.pwm_mod = *ports.lfo_pwm_mod;
+
+        // adjust MSEG
+        let mseg = &mut self.synth.mseg;
+        let points = [
+            (*ports.mseg_p1_level, *ports.mseg_p1_time, *ports.mseg_p1_curve),
+            (*ports.mseg_p2_level, *ports.mseg_p2_time, *ports.mseg_p2_curve),
+            (*ports.mseg_p3_level, *ports.mseg_p3_time, *ports.mseg_p3_curve),
+            (*ports.mseg_p4_level, *ports.mseg_p4_time, *ports.mseg_p4_curve),
+            (*ports.mseg_p5_level, *ports.mseg_p5_time, *ports.mseg_p5_curve),
+            (*ports.mseg_p6_level, *ports.mseg_p6_time, *ports.mseg_p6_curve),
+        ];
+        for (point, (level, time, curve)) in mseg.points.iter_mut().zip(points) {
+            point.level = level;
+            point.time = zero_at_minimum(time);
+            point.curve = curve;
+        }
+        mseg.point_count = (*ports.mseg_points as usize).clamp(1, MSEG_POINTS);
+        mseg.loop_mode = match *ports.mseg_loop {
+            x if x < 1.0 => MsegLoop::Off,
+            x if x < 2.0 => MsegLoop::WhileHeld,
+            _ => MsegLoop::Always,
+        };
+        // Points are numbered from 1 in the UI
+        mseg.loop_start = (*ports.mseg_loop_start as usize).saturating_sub(1);
+        mseg.loop_end = (*ports.mseg_loop_end as usize).saturating_sub(1);
+        mseg.sustain_point = (*ports.mseg_sustain as usize).checked_sub(1);
+        mseg.tempo_sync = *ports.mseg_sync >= 1.0;
+        mseg.bpm = *ports.mseg_bpm;
+        mseg.destination = match *ports.mseg_destination {
+            x if x < 1.0 => MsegDestination::Off,
+            x if x < 2.0 => MsegDestination::Pitch(None),
+            x if x < 3.0 => MsegDestination::Pitch(Some(0)),
+            x if x < 4.0 => MsegDestination::Pitch(Some(1)),
+            x if x < 5.0 => MsegDestination::Pitch(Some(2)),
+            x if x < 6.0 => MsegDestination::Amplitude(0),
+            x if x < 7.0 => MsegDestination::Amplitude(1),
+            x if x < 8.0 => MsegDestination::Amplitude(2),
+            x if x < 9.0 => MsegDestination::FilterCutoff,
+            x if x < 10.0 => MsegDestination::FilterResonance,
+            x if x < 11.0 => MsegDestination::Volume,
+            x if x < 12.0 => MsegDestination::WavetablePosition(None),
+            x if x < 13.0 => MsegDestination::WavetablePosition(Some(0)),
+            x if x < 14.0 => MsegDestination::WavetablePosition(Some(1)),
+            _ => MsegDestination::WavetablePosition(Some(2)),
+        };
+        mseg.amount = *ports.mseg_amount;
+
+        // velocity
+        let velocity = &mut self.synth.velocity_params;
+        velocity.amp_amount = *ports.vel_amp_amount;
+        velocity.amp_curve = match *ports.vel_amp_curve {
+            x if x < 1.0 => VelocityCurve::Linear,
+            x if x < 2.0 => VelocityCurve::Exponential,
+            x if x < 3.0 => VelocityCurve::Logarithmic,
+            _ => VelocityCurve::Fixed,
+        };
+        velocity.cutoff = *ports.vel_cutoff;
+        velocity.attack = *ports.vel_attack;
+        velocity.osc_amp = [*ports.vel_osc1_amp, *ports.vel_osc2_amp, *ports.vel_osc3_amp];
+        velocity.osc_mod = [*ports.vel_osc1_mod, *ports.vel_osc2_mod, *ports.vel_osc3_mod];
+
+        // key scaling
+        let key_scaling = &mut self.synth.key_scaling;
+        key_scaling.breakpoint = *ports.key_breakpoint;
+        key_scaling.low_depth = *ports.key_low_level;
+        key_scaling.high_depth = *ports.key_high_level;
+        key_scaling.rate = *ports.key_env_rate;
 
         // apply oscillator ports
         // ... TODO: write a macro for all this
@@ -233,10 +499,21 @@ impl Plugin for SynthLv2 {
             self.synth.oscillators[0].voices_detune = (*ports.osc1_super_detune / 100.0).powi(3);
             self.synth.oscillators[0].phase = *ports.osc1_phase * 2.0 * PI / 100.0;
             self.synth.oscillators[0].phase_rand = *ports.osc1_phase_rand * 2.0 * PI / 100.0;
-            self.synth.oscillators[0].wave = OscWave::from_index(*ports.osc1_wave);
-            self.synth.oscillators[0].pm = (*ports.osc1_pm).powi(2);
-            self.synth.oscillators[0].fm = (*ports.osc1_fm).powi(2);
-            self.synth.oscillators[0].am = (*ports.osc1_am).powi(2);
+            self.synth.oscillators[0].wave = OscWave::from_index(*ports.osc1_wave)
+                .with_user_table(self.user_tables.select(0, *ports.osc1_user_table));
+            self.synth.oscillators[0].engine = OscEngine::from_index(*ports.osc1_engine);
+            self.synth.oscillators[0].wavetable_position = *ports.osc1_wt_position;
+            self.synth.oscillators[0].wavetable_morph = match *ports.osc1_wt_morph {
+                x if x < 1.0 => WavetableMorph::Crossfade,
+                _ => WavetableMorph::Spectral,
+            };
+            self.synth.oscillators[0].sync = SyncMode::from_index(*ports.osc1_sync);
+            self.synth.oscillators[0].pulse_width = *ports.osc1_pwm * 2.0 * PI / 100.0;
+            self.synth.oscillators[0].pwm_env = *ports.osc1_pwm_env * 2.0 * PI / 100.0;
+            self.synth.oscillators[0].pwm_osc = *ports.osc1_pwm_osc;
+            self.synth.oscillators[0].skew = *ports.osc1_skew / 100.0;
+            self.synth.oscillators[0].feedback = *ports.osc1_feedback;
+            self.synth.oscillators[0].fm_mode = FmMode::from_index(*ports.osc1_fm_mode);
             
             // osc2
             self.synth.oscillators[1].amp = *ports.osc2_amp / 100.0;
@@ -251,10 +528,21 @@ impl Plugin for SynthLv2 {
             self.synth.oscillators[1].voices_detune = (*ports.osc2_super_detune / 100.0).powi(3);
             self.synth.oscillators[1].phase = *ports.osc2_phase * 2.0 * PI / 100.0;
             self.synth.oscillators[1].phase_rand = *ports.osc2_phase_rand * 2.0 * PI / 100.0;
-            self.synth.oscillators[1].wave = OscWave::from_index(*ports.osc2_wave);
-            self.synth.oscillators[1].pm = (*ports.osc2_pm).powi(2);
-            self.synth.oscillators[1].fm = (*ports.osc2_fm).powi(2);
-            self.synth.oscillators[1].am = (*ports.osc2_am).powi(2);
+            self.synth.oscillators[1].wave = OscWave::from_index(*ports.osc2_wave)
+                .with_user_table(self.user_tables.select(1, *ports.osc2_user_table));
+            self.synth.oscillators[1].engine = OscEngine::from_index(*ports.osc2_engine);
+            self.synth.oscillators[1].wavetable_position = *ports.osc2_wt_position;
+            self.synth.oscillators[1].wavetable_morph = match *ports.osc2_wt_morph {
+                x if x < 1.0 => WavetableMorph::Crossfade,
+                _ => WavetableMorph::Spectral,
+            };
+            self.synth.oscillators[1].sync = SyncMode::from_index(*ports.osc2_sync);
+            self.synth.oscillators[1].pulse_width = *ports.osc2_pwm * 2.0 * PI / 100.0;
+            self.synth.oscillators[1].pwm_env = *ports.osc2_pwm_env * 2.0 * PI / 100.0;
+            self.synth.oscillators[1].pwm_osc = *ports.osc2_pwm_osc;
+            self.synth.oscillators[1].skew = *ports.osc2_skew / 100.0;
+            self.synth.oscillators[1].feedback = *ports.osc2_feedback;
+            self.synth.oscillators[1].fm_mode = FmMode::from_index(*ports.osc2_fm_mode);
             
             // osc3
             self.synth.oscillators[2].amp = *ports.osc3_amp / 100.0;
@@ -269,9 +557,94 @@ impl Plugin for SynthLv2 {
             self.synth.oscillators[2].voices_detune = (*ports.osc3_super_detune / 100.0).powi(3);
             self.synth.oscillators[2].phase = *ports.osc3_phase * 2.0 * PI / 100.0;
             self.synth.oscillators[2].phase_rand = *ports.osc3_phase_rand * 2.0 * PI / 100.0;
-            self.synth.oscillators[2].wave = OscWave::from_index_pulse(*ports.osc3_wave);
+            self.synth.oscillators[2].wave = OscWave::from_index_pulse(*ports.osc3_wave)
+                .with_user_table(self.user_tables.select(2, *ports.osc3_user_table));
+            self.synth.oscillators[2].engine = OscEngine::from_index(*ports.osc3_engine);
+            self.synth.oscillators[2].wavetable_position = *ports.osc3_wt_position;
+            self.synth.oscillators[2].wavetable_morph = match *ports.osc3_wt_morph {
+                x if x < 1.0 => WavetableMorph::Crossfade,
+                _ => WavetableMorph::Spectral,
+            };
+            self.synth.oscillators[2].sync = SyncMode::from_index(*ports.osc3_sync);
             self.synth.oscillators[2].pulse_width = *ports.osc3_pwm * 2.0 * PI / 100.0;
+            self.synth.oscillators[2].pwm_env = *ports.osc3_pwm_env * 2.0 * PI / 100.0;
+            self.synth.oscillators[2].pwm_osc = *ports.osc3_pwm_osc;
+            self.synth.oscillators[2].skew = *ports.osc3_skew / 100.0;
+            self.synth.oscillators[2].feedback = *ports.osc3_feedback;
+            self.synth.oscillators[2].fm_mode = FmMode::from_index(*ports.osc3_fm_mode);
         }
+
+        // modulation algorithm, with amounts indexed by [destination][source]
+        let amounts = |pm: f32, fm: f32, am: f32, rm: f32| ModAmounts {
+            pm: pm.powi(2),
+            fm,
+            am: am.powi(2),
+            rm: rm.powi(2),
+        };
+        let modulation = &mut self.synth.modulation;
+        modulation.algorithm = Algorithm::from_index(*ports.mod_algorithm);
+        modulation.amounts[0][1] = amounts(*ports.osc1_pm, *ports.osc1_fm, *ports.osc1_am, *ports.osc1_rm);
+        modulation.amounts[1][2] = amounts(*ports.osc2_pm, *ports.osc2_fm, *ports.osc2_am, *ports.osc2_rm);
+        modulation.amounts[2][0] = amounts(*ports.osc3_pm, *ports.osc3_fm, *ports.osc3_am, *ports.osc3_rm);
+        modulation.amounts[0][2] = amounts(*ports.mod_3_to_1_pm, *ports.mod_3_to_1_fm, *ports.mod_3_to_1_am, *ports.mod_3_to_1_rm);
+        modulation.amounts[1][0] = amounts(*ports.mod_1_to_2_pm, *ports.mod_1_to_2_fm, *ports.mod_1_to_2_am, *ports.mod_1_to_2_rm);
+        modulation.amounts[2][1] = amounts(*ports.mod_2_to_3_pm, *ports.mod_2_to_3_fm, *ports.mod_2_to_3_am, *ports.mod_2_to_3_rm);
+
+        // noise generator
+        self.synth.noise.noise_type = NoiseType::from_index(*ports.noise_type);
+        self.synth.noise.level = *ports.noise_level / 100.0;
+        self.synth.noise.rate = *ports.noise_rate;
+
+        // ring modulator
+        self.synth.ring.sources = RingSources::from_index(*ports.ring_sources);
+        self.synth.ring.level = *ports.ring_level / 100.0;
+
+        // harmonic editor
+        let harmonics = &mut self.synth.harmonics;
+        harmonics.rolloff = *ports.harm_rolloff;
+        harmonics.parity = match *ports.harm_parity {
+            x if x < 1.0 => PartialParity::All,
+            x if x < 2.0 => PartialParity::Odd,
+            _ => PartialParity::Even,
+        };
+        harmonics.every = *ports.harm_every as usize;
+        harmonics.count = *ports.harm_count as usize;
+        harmonics.gains = [
+            *ports.harm_p1_gain,
+            *ports.harm_p2_gain,
+            *ports.harm_p3_gain,
+            *ports.harm_p4_gain,
+            *ports.harm_p5_gain,
+            *ports.harm_p6_gain,
+            *ports.harm_p7_gain,
+            *ports.harm_p8_gain,
+            *ports.harm_p9_gain,
+            *ports.harm_p10_gain,
+            *ports.harm_p11_gain,
+            *ports.harm_p12_gain,
+            *ports.harm_p13_gain,
+            *ports.harm_p14_gain,
+            *ports.harm_p15_gain,
+            *ports.harm_p16_gain
+        ];
+        harmonics.phases = [
+            *ports.harm_p1_phase,
+            *ports.harm_p2_phase,
+            *ports.harm_p3_phase,
+            *ports.harm_p4_phase,
+            *ports.harm_p5_phase,
+            *ports.harm_p6_phase,
+            *ports.harm_p7_phase,
+            *ports.harm_p8_phase,
+            *ports.harm_p9_phase,
+            *ports.harm_p10_phase,
+            *ports.harm_p11_phase,
+            *ports.harm_p12_phase,
+            *ports.harm_p13_phase,
+            *ports.harm_p14_phase,
+            *ports.harm_p15_phase,
+            *ports.harm_p16_phase
+        ];
         
         let control_sequence = ports
         .midi
@@ -316,6 +689,21 @@ impl Plugin for SynthLv2 {
         // run synthesiser
         self.synth.run(&mut ports.out_l, &mut ports.out_r);
     }
+
+    fn extension_data(uri: &Uri) -> Option<&'static dyn std::any::Any> {
+        match_extensions![uri, StateDescriptor]
+    }
+}
+
+/// Treats a time port's minimum value of 0.001 as 0.0. This is a workaround to make
+/// logarithmic values display nicely in Ardour (which ignores the 'logarithmic' port
+/// property when the port's minimum value is 0) while still allowing instant times.
+fn zero_at_minimum(time: f32) -> f32 {
+    if time <= 0.001 {
+        0.0
+    } else {
+        time
+    }
 }
 // The `lv2_descriptors` macro creates the entry point to the plugin library. It takes structs that implement `Plugin` and exposes them. The host will load the library and call a generated function to find all the plugins defined in the library.
 lv2_descriptors!(SynthLv2);
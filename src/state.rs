@@ -0,0 +1,206 @@
+use std::ffi::{c_void, CStr};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use lv2::lv2_sys as sys;
+use lv2::prelude::{ExtensionDescriptor, LV2Map, Map, UriBound};
+
+use crate::SynthLv2;
+
+/// URIDs of the keys and value types saved in the plugin state.
+pub struct StateKeys {
+    /// File name of each oscillator's user wavetable.
+    names: [u32; 3],
+    /// Value of each oscillator's User Table port when the state was saved.
+    ports: [u32; 3],
+    string: u32,
+    float: u32,
+}
+impl StateKeys {
+    pub fn map(map: &LV2Map, string: u32, float: u32) -> Option<Self> {
+        let key = |osc: usize, suffix: &str| {
+            let uri = format!("https://github.com/Madadog/three_osc#osc{osc}_user_table{suffix}\0");
+            Some(
+                map.map_uri(CStr::from_bytes_with_nul(uri.as_bytes()).ok()?)?
+                    .get(),
+            )
+        };
+        Some(Self {
+            names: [key(1, "")?, key(2, "")?, key(3, "")?],
+            ports: [key(1, "_port")?, key(2, "_port")?, key(3, "_port")?],
+            string,
+            float,
+        })
+    }
+}
+
+/// Which user wavetable each oscillator plays.
+///
+/// User Table ports count through the bundle's wavetables alphabetically, so adding or
+/// renaming a file changes which table an index points at. The plugin state saves the file
+/// name instead, and a restored patch plays that file for as long as its port stays at the
+/// value it was saved with.
+pub struct UserTables {
+    /// File names of the loaded tables, in the order the ports count through them.
+    names: Vec<String>,
+    /// Latest value of each oscillator's User Table port, as bits. Written by `run` and read
+    /// by `save`, which hosts may call at the same time.
+    ports: [AtomicU32; 3],
+    /// Table restored by name for each oscillator, with the port value it was saved at.
+    restored: [Option<(f32, usize)>; 3],
+    keys: StateKeys,
+}
+impl UserTables {
+    pub fn new(names: Vec<String>, keys: StateKeys) -> Self {
+        Self {
+            names,
+            ports: Default::default(),
+            restored: [None; 3],
+            keys,
+        }
+    }
+    fn resolve(&self, osc: usize, port: f32) -> usize {
+        match self.restored[osc] {
+            Some((saved, index)) if saved == port => index,
+            _ => (port as usize).saturating_sub(1),
+        }
+    }
+    /// Returns the index of the table oscillator `osc` plays with its User Table port at
+    /// `port`.
+    pub fn select(&self, osc: usize, port: f32) -> usize {
+        self.ports[osc].store(port.to_bits(), Ordering::Relaxed);
+        self.resolve(osc, port)
+    }
+    /// Saves the file name of each oscillator's table, and the port value which picks it.
+    fn save_with(&self, mut store: impl FnMut(u32, &[u8], u32)) {
+        for osc in 0..3 {
+            let port = f32::from_bits(self.ports[osc].load(Ordering::Relaxed));
+            if let Some(name) = self.names.get(self.resolve(osc, port)) {
+                let name = format!("{name}\0");
+                store(self.keys.names[osc], name.as_bytes(), self.keys.string);
+                store(self.keys.ports[osc], &port.to_ne_bytes(), self.keys.float);
+            }
+        }
+    }
+    /// Finds each oscillator's saved file name among the loaded tables. Oscillators whose
+    /// file is missing fall back to their port's index.
+    fn restore_with<'a>(&mut self, retrieve: impl Fn(u32) -> Option<(&'a [u8], u32)>) {
+        for osc in 0..3 {
+            let name = retrieve(self.keys.names[osc])
+                .filter(|(_, type_)| *type_ == self.keys.string)
+                .and_then(|(value, _)| CStr::from_bytes_until_nul(value).ok()?.to_str().ok());
+            let port = retrieve(self.keys.ports[osc])
+                .filter(|(value, type_)| *type_ == self.keys.float && value.len() == 4)
+                .map(|(value, _)| f32::from_ne_bytes(value.try_into().unwrap()));
+            self.restored[osc] = match (name, port) {
+                (Some(name), Some(port)) => self
+                    .names
+                    .iter()
+                    .position(|x| x == name)
+                    .map(|index| (port, index)),
+                _ => None,
+            };
+        }
+    }
+}
+
+/// Descriptor for the LV2 state extension.
+pub struct StateDescriptor;
+unsafe impl UriBound for StateDescriptor {
+    const URI: &'static [u8] = sys::LV2_STATE__interface;
+}
+impl StateDescriptor {
+    unsafe extern "C" fn save(
+        instance: sys::LV2_Handle,
+        store: sys::LV2_State_Store_Function,
+        handle: sys::LV2_State_Handle,
+        _flags: u32,
+        _features: *const *const sys::LV2_Feature,
+    ) -> sys::LV2_State_Status {
+        // The plugin is the first field of the instance, so this is a valid pointer to it
+        let (Some(plugin), Some(store)) = ((instance as *const SynthLv2).as_ref(), store) else {
+            return sys::LV2_State_Status_LV2_STATE_ERR_UNKNOWN;
+        };
+        let flags =
+            sys::LV2_State_Flags::LV2_STATE_IS_POD | sys::LV2_State_Flags::LV2_STATE_IS_PORTABLE;
+        plugin.user_tables.save_with(|key, value, type_| {
+            store(
+                handle,
+                key,
+                value.as_ptr() as *const c_void,
+                value.len(),
+                type_,
+                flags.0,
+            );
+        });
+        sys::LV2_State_Status_LV2_STATE_SUCCESS
+    }
+    unsafe extern "C" fn restore(
+        instance: sys::LV2_Handle,
+        retrieve: sys::LV2_State_Retrieve_Function,
+        handle: sys::LV2_State_Handle,
+        _flags: u32,
+        _features: *const *const sys::LV2_Feature,
+    ) -> sys::LV2_State_Status {
+        let (Some(plugin), Some(retrieve)) = ((instance as *mut SynthLv2).as_mut(), retrieve)
+        else {
+            return sys::LV2_State_Status_LV2_STATE_ERR_UNKNOWN;
+        };
+        plugin.user_tables.restore_with(|key| {
+            let (mut size, mut type_, mut flags) = (0, 0, 0);
+            let value = retrieve(handle, key, &mut size, &mut type_, &mut flags);
+            if value.is_null() {
+                None
+            } else {
+                Some((std::slice::from_raw_parts(value as *const u8, size), type_))
+            }
+        });
+        sys::LV2_State_Status_LV2_STATE_SUCCESS
+    }
+}
+impl ExtensionDescriptor for StateDescriptor {
+    type ExtensionInterface = sys::LV2_State_Interface;
+
+    const INTERFACE: &'static sys::LV2_State_Interface = &sys::LV2_State_Interface {
+        save: Some(Self::save),
+        restore: Some(Self::restore),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn tables(names: &[&str]) -> UserTables {
+        let keys = StateKeys {
+            names: [1, 2, 3],
+            ports: [4, 5, 6],
+            string: 7,
+            float: 8,
+        };
+        UserTables::new(names.iter().map(|x| x.to_string()).collect(), keys)
+    }
+
+    #[test]
+    fn restored_patches_keep_their_file_when_others_are_added() {
+        let saved = tables(&["bell", "organ", "pad"]);
+        assert_eq!(saved.select(0, 2.0), 1);
+        let mut state = HashMap::new();
+        saved.save_with(|key, value, type_| {
+            state.insert(key, (value.to_vec(), type_));
+        });
+
+        // A new file sorts before the saved one, moving it up an index
+        let mut restored = tables(&["bell", "brass", "organ", "pad"]);
+        restored.restore_with(|key| state.get(&key).map(|(value, type_)| (&value[..], *type_)));
+        assert_eq!(restored.select(0, 2.0), 2);
+        // Moving the port picks tables by index again
+        assert_eq!(restored.select(0, 4.0), 3);
+
+        // Without the file, the port's index is used
+        let mut missing = tables(&["bell", "pad"]);
+        missing.restore_with(|key| state.get(&key).map(|(value, type_)| (&value[..], *type_)));
+        assert_eq!(missing.select(0, 2.0), 1);
+    }
+}
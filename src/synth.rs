@@ -1,34 +1,64 @@
 use std::f32::consts::PI;
+use std::path::Path;
 
 use itertools::izip;
 
-use self::envelopes::AdsrEnvelope;
+use self::algorithm::ModulationParams;
+use self::envelopes::{DahdsrEnvelope, EnvelopeVoice};
+use self::mseg::{Mseg, MsegVoice};
+use self::noise::{NoiseParams, NoiseVoice};
+use self::filter::drive::DcBlocker;
 use self::filter::Filter;
-use self::filter::FilterContainer;
+use self::harmonics::HarmonicParams;
+use self::key_scaling::KeyScaling;
 use self::notes::Notes;
 use self::oscillator::OscVoice;
 use self::oscillator::OscWave;
 use self::oscillator::modulate_delta;
 use self::oscillator::OscillatorParams;
+use self::oscillator::SmoothedOscillator;
 use self::oscillator::SuperVoice;
 use self::oscillator::WavetableSet;
+use self::oversampling::{DecimationFilter, Decimator, Delay, Oversampling, MAX_FACTOR};
+use self::ring::RingModParams;
+use self::smoothing::Smoother;
+use self::smoothing::SmoothingBlock;
+use self::smoothing::SmoothingParams;
+use self::velocity::VelocityParams;
+
+/// Length of every bandlimited wavetable.
+const WAVETABLE_LEN: usize = 2048;
+/// Phase offset in radians from an oscillator output of 1.0 fed back at full amount.
+const FEEDBACK_DEPTH: f32 = 1.5;
 
 pub struct ThreeOsc {
     pub voices: Vec<Voice>,
     pub notes: Notes,
-    pub gain_envelope: AdsrEnvelope,
+    pub gain_envelope: DahdsrEnvelope,
     pub(crate) filter_controller: filter::FilterController,
     pub sample_rate: f64,
     pub output_volume: f32,
     pub oscillators: [OscillatorParams; 3],
+    pub modulation: ModulationParams,
     pub waves: WavetableSet,
+    pub harmonics: HarmonicParams,
     pub bend_range: f32,
     pub polyphony: Polyphony,
     pub octave_detune: f32,
     pub portamento_rate: f32,
     pub portamento_offset: f32,
     pub lfo_params: LfoParams,
+    pub mseg: Mseg,
+    pub noise: NoiseParams,
+    pub ring: RingModParams,
+    pub velocity_params: VelocityParams,
+    pub key_scaling: KeyScaling,
     pub stereo_width: Option<f32>,
+    pub smoothing: SmoothingParams,
+    /// Oversampling of the oscillators, which are decimated back down before the filter.
+    pub oversampling: Oversampling,
+    decimation_filter: DecimationFilter,
+    output_volume_smoother: Smoother,
 }
 
 impl ThreeOsc {
@@ -36,7 +66,7 @@ impl ThreeOsc {
         Self {
             voices: Vec::with_capacity(64),
             notes: Notes::new(),
-            gain_envelope: AdsrEnvelope::new(0.0, 0.5, 0.05, 1.0, 1.0),
+            gain_envelope: DahdsrEnvelope::new(0.0, 0.5, 0.05, 1.0, 1.0),
             filter_controller: filter::FilterController::new(),
             sample_rate,
             output_volume: 0.3,
@@ -45,17 +75,34 @@ impl ThreeOsc {
                 OscillatorParams::default(),
                 OscillatorParams::default(),
             ],
+            modulation: Default::default(),
             // wavetables: WavetableNotes::from_additive_osc_2(&AdditiveOsc::saw(), sample_rate as f32, 1.0, 2048),
-            waves: WavetableSet::new(sample_rate as f32, 2048),
+            waves: WavetableSet::new(sample_rate as f32, WAVETABLE_LEN),
+            harmonics: Default::default(),
             bend_range: 2.0,
             polyphony: Polyphony::Polyphonic,
             octave_detune: 1.0,
             portamento_rate: 0.1,
             portamento_offset: 0.0,
             lfo_params: Default::default(),
+            mseg: Default::default(),
+            noise: Default::default(),
+            ring: Default::default(),
+            velocity_params: Default::default(),
+            key_scaling: Default::default(),
             stereo_width: None,
+            smoothing: Default::default(),
+            oversampling: Oversampling::Off,
+            decimation_filter: DecimationFilter::new(1),
+            output_volume_smoother: Default::default(),
         }
     }
+    /// Loads every WAV file in `directory` as a user wavetable. Slow, so this must not be
+    /// called from the audio thread.
+    pub fn load_user_wavetables(&mut self, directory: &Path) {
+        self.waves
+            .load_user_tables(directory, self.sample_rate as f32, WAVETABLE_LEN);
+    }
     pub fn note_on(&mut self, note: u8, velocity: u8) {
         self.notes.note_on(note, velocity);
 
@@ -73,11 +120,10 @@ impl ThreeOsc {
 
                     // If the note is released, or if we are in monophonic mode, retrigger it.
                     if matches!(self.polyphony, Polyphony::Monophonic)
-                        || voice.release_time.is_some()
+                        || voice.released
                     {
                         voice.semitone_detune += self.portamento_offset;
-                        voice.release_time = None;
-                        voice.runtime = 0;
+                        voice.retrigger();
                     }
                 } else {
                     let mut new_voice = Voice::from_midi_note(note, velocity, &self.oscillators);
@@ -95,7 +141,7 @@ impl ThreeOsc {
                 .voices
                 .iter_mut()
                 .filter(|voice| voice.id == note as u32)
-                .for_each(|voice| voice.release()),
+                .for_each(|voice| voice.release(&self.mseg)),
             // TODO: If there are two notes each with their own voice playing, when one is released
             // it will snap to the other note, resulting in two voices playing the same note at the
             // same time, which sounds bad. Stop this behaviour by filtering out notes with voices
@@ -120,172 +166,326 @@ impl ThreeOsc {
                         // Retrigger notes when releasing keys in Monophonic mode
                         if matches!(self.polyphony, Polyphony::Monophonic) {
                             voice.semitone_detune += self.portamento_offset;
-                            voice.release_time = None;
-                            voice.runtime = 0;
+                            voice.retrigger();
                         }
                     } else {
-                        voice.release();
+                        voice.release(&self.mseg);
                     }
                 }),
         }
     }
     pub fn release_voices(&mut self) {
-        let duration = (self.gain_envelope.release_time * self.sample_rate as f32) as u32;
-
-        self.voices.retain(|voice| {
-            if let Some(release_time) = voice.release_time {
-                voice.runtime - release_time < duration
-            } else {
-                true
-            }
-        })
+        self.voices
+            .retain(|voice| !voice.gain_envelope.is_finished())
     }
     pub fn run(&mut self, output_left: &mut [f32], output_right: &mut [f32]) {
         self.release_voices();
-        self.filter_controller.cutoff = self.filter_controller.target_cutoff;
+        self.waves.harmonics.update(&self.harmonics);
+
+        let smoothing = self
+            .smoothing
+            .block(output_left.len(), self.sample_rate as f32);
 
         // Minor optimisation: Semitone / octave / pitch multiplier offset is calculated and cached
         // once per run, not once per sample
         for osc in self.oscillators.iter_mut() {
             osc.update_total_pitch();
             osc.update_unison_amp();
+            osc.update_smoothing(&smoothing);
         }
+        self.modulation.update_smoothing(&smoothing);
+        self.filter_controller.update_smoothing(&smoothing);
+        self.lfo_params.update_smoothing(&smoothing);
+        self.noise.update_smoothing(&smoothing);
+        self.ring.update_smoothing(&smoothing);
+        self.output_volume_smoother
+            .next_block(self.output_volume, &smoothing);
 
         let lfo_delta = self.lfo_params.delta(self.sample_rate as f32);
 
+        let factor = self.oversampling.factor();
+        if self.decimation_filter.factor() != factor {
+            self.decimation_filter = DecimationFilter::new(factor);
+            // Their history was filtered for the old factor
+            for voice in self.voices.iter_mut() {
+                voice.decimators = Default::default();
+                voice.noise_delay = Default::default();
+            }
+        }
+        // Noise skips the decimator, so it's delayed to stay in time with the oscillators.
+        // Envelopes and modulation aren't, and reach the oscillators this many samples early.
+        let decimation_delay = self.decimation_filter.delay();
+        let oscillator_rate = self.sample_rate as f32 * factor as f32;
+
         // Write samples from all voices
         for voice in self.voices.iter_mut() {
             let velocity = voice.velocity as f32 / 128.0;
+            let velocity_amp = self.velocity_params.amp(velocity);
+            let velocity_cutoff = self.velocity_params.cutoff_mult(velocity);
+            let velocity_osc_amp = [0, 1, 2].map(|i| self.velocity_params.osc_amp(velocity, i));
+            let velocity_osc_mod = [0, 1, 2].map(|i| self.velocity_params.osc_mod(velocity, i));
+            let attack_scale = self.velocity_params.attack_scale(velocity);
+            voice.gain_envelope.set_attack_scale(attack_scale);
+            voice.filter_envelope.set_attack_scale(attack_scale);
+            let key_amp = self.key_scaling.amp_mult(voice.id as f32);
+            let envelope_time_step =
+                self.key_scaling.rate_mult(voice.id as f32) / self.sample_rate as f32;
 
             voice.pitch_multiply = self.octave_detune;
 
-            let drive = match voice.filter {
-                // Biquad/SVF/none filters are unaffected by drive, so we clamp it between 0 and 1 to
-                // keep the levels the same when switching filter.
-                FilterContainer::BiquadFilter(_) | FilterContainer::SvfSimperFilter(_) | FilterContainer::None => {
-                    self.filter_controller.drive.min(1.0)
-                }
-                FilterContainer::LadderFilter(_) => {
-                    self.filter_controller.drive / 2.0
-                }
-                _ => {self.filter_controller.drive}
-            };
-
-            for (out_l, out_r) in izip!(output_left.iter_mut(), output_right.iter_mut()) {
-                let mut out = 0.0;
-                let mut out_stereo = 0.0;
-
-                voice.advance();
-                let envelope_index = voice.runtime as f32 / self.sample_rate as f32;
+            for (sample_index, (out_l, out_r)) in
+                izip!(output_left.iter_mut(), output_right.iter_mut()).enumerate()
+            {
+                let oscillators = [
+                    self.oscillators[0].smoothed(sample_index),
+                    self.oscillators[1].smoothed(sample_index),
+                    self.oscillators[2].smoothed(sample_index),
+                ];
+                let mut filter_controls = self.filter_controller.smoothed(sample_index);
+                let lfo_controls = self.lfo_params.smoothed(sample_index);
+
+                let time_step = 1.0 / self.sample_rate as f32;
+                let gain_envelope = voice
+                    .gain_envelope
+                    .next(&self.gain_envelope, envelope_time_step);
+                let filter_envelope = voice
+                    .filter_envelope
+                    .next(&self.filter_controller.cutoff_envelope, envelope_time_step);
+                let mseg = voice.mseg.next(&self.mseg, time_step);
                 
                 let delta = voice.delta(self.sample_rate as f32);
                 
                 let mut osc_delta = [0.0; 3];
-                for (x, osc) in izip!(osc_delta.iter_mut(), self.oscillators.iter()) {
-                    *x = osc.total_pitch_multiplier() * delta;
-                }
-                
-                for osc in self.oscillators.iter_mut() {
-                    if let OscWave::Pulse { width } = &mut osc.wave {
-                        *width = osc.pulse_width;
-                    }
+                for (i, (x, osc)) in izip!(osc_delta.iter_mut(), oscillators.iter()).enumerate() {
+                    *x = osc.pitch_multiplier * delta * self.mseg.pitch_mult(mseg, i);
                 }
                 
                 voice.semitone_detune = lerp(voice.semitone_detune, 0.0, self.portamento_rate);
                 
-                let keytrack_freq = 2.0_f32.powf(
-                    (voice.id as f32 - 69.0 + voice.semitone_detune) / 12.0
-                        * self.filter_controller.keytrack,
-                );
-
                 let lfo_phase = voice.lfo.add_phase(lfo_delta);
                 let lfo = self.lfo_params.wave.generate(lfo_phase);
                 
-                let mut osc_lfo_amp = [1.0; 3];
+                let mut osc_lfo_amp = [
+                    self.mseg.amp_mult(mseg, 0) * velocity_osc_amp[0],
+                    self.mseg.amp_mult(mseg, 1) * velocity_osc_amp[1],
+                    self.mseg.amp_mult(mseg, 2) * velocity_osc_amp[2],
+                ];
                 let mut osc_lfo_mod = [1.0; 3];
+                let mut osc_position = [
+                    oscillators[0].wavetable_position + self.mseg.position_offset(mseg, 0),
+                    oscillators[1].wavetable_position + self.mseg.position_offset(mseg, 1),
+                    oscillators[2].wavetable_position + self.mseg.position_offset(mseg, 2),
+                ];
+                let mut osc_pulse_width = [
+                    oscillators[0].pulse_width + filter_envelope * oscillators[0].pwm_env,
+                    oscillators[1].pulse_width + filter_envelope * oscillators[1].pwm_env,
+                    oscillators[2].pulse_width + filter_envelope * oscillators[2].pwm_env,
+                ];
 
                 // set / bypass modulation depending on LFO target 
                 match self.lfo_params.target_osc {
                     Some(x) => {
-                        osc_lfo_amp[x] = lerp(1.0, (lfo + 1.) / 2.0, self.lfo_params.amp_mod);
-                        if let Some(lfo_mod) = osc_lfo_mod.get_mut(x + 1) {
-                            *lfo_mod = lerp(1.0, (lfo + 1.) / 2.0, self.lfo_params.mod_mod);
-                        };
-                        osc_delta[x] = osc_delta[x] + osc_delta[x] * lfo * self.lfo_params.freq_mod;
+                        osc_lfo_amp[x] *= lerp(1.0, (lfo + 1.) / 2.0, lfo_controls.amp_mod);
+                        osc_lfo_mod[x] = lerp(1.0, (lfo + 1.) / 2.0, lfo_controls.mod_mod);
+                        osc_delta[x] = osc_delta[x] + osc_delta[x] * lfo * lfo_controls.freq_mod;
+                        osc_position[x] += lfo * lfo_controls.position_mod;
+                        osc_pulse_width[x] += lfo * lfo_controls.pwm_mod * PI;
                     },
                     None => {
-                        for (lfo_amp, lfo_mod, delta) in izip!(osc_lfo_amp.iter_mut(), osc_lfo_mod.iter_mut(), osc_delta.iter_mut()) {
-                            *lfo_amp = lerp(1.0, (lfo + 1.) / 2.0, self.lfo_params.amp_mod);
-                            *lfo_mod = lerp(1.0, (lfo + 1.) / 2.0, self.lfo_params.mod_mod);
-                            *delta = *delta + *delta * lfo * self.lfo_params.freq_mod;
+                        for (lfo_amp, lfo_mod, delta, position, pulse_width) in izip!(osc_lfo_amp.iter_mut(), osc_lfo_mod.iter_mut(), osc_delta.iter_mut(), osc_position.iter_mut(), osc_pulse_width.iter_mut()) {
+                            *lfo_amp *= lerp(1.0, (lfo + 1.) / 2.0, lfo_controls.amp_mod);
+                            *lfo_mod = lerp(1.0, (lfo + 1.) / 2.0, lfo_controls.mod_mod);
+                            *delta = *delta + *delta * lfo * lfo_controls.freq_mod;
+                            *position += lfo * lfo_controls.position_mod;
+                            *pulse_width += lfo * lfo_controls.pwm_mod * PI;
                         }
                     }
                 };
 
-                self.oscillators.iter_mut()
-                .enumerate()
-                .rev()
-                .fold(0.0, |mod_osc_out, (i, osc)| {
-                    let delta = modulate_delta(osc_delta[i], mod_osc_out * osc.fm);
-
-                    let phases = voice.osc_voice[i].unison_phases(
-                        delta,
-                        osc.voice_count.into(),
-                        osc.voices_detune,
-                    );
-
-                    if let Some(width) = self.stereo_width {
-                        let (mut osc_out_l, mut osc_out_r) = self.waves.select(&osc.wave).delta_index(osc_delta[i], self.sample_rate as f32)
-                            .generate_multi_stereo_pm(
-                            &phases,
+                // Oscillators run `factor` times per sample, each step moving a fraction of the
+                // way, then are decimated back to the sample rate
+                let osc_delta = osc_delta.map(|x| x / factor as f32);
+                let mut oversampled_l = [0.0; MAX_FACTOR];
+                let mut oversampled_r = [0.0; MAX_FACTOR];
+                // Noise has no pitch or phase, so it ignores unison and any modulation it
+                // receives. It's made once per sample and held through the steps, then skips
+                // the decimator, which would filter out its top octaves. Modulators still hear
+                // it at every step.
+                let osc_noise: [Option<f32>; 3] = std::array::from_fn(|i| match self.oscillators[i].wave {
+                    OscWave::Noise(noise) => Some(voice.osc_noise[i].next(
+                        noise,
+                        self.noise.rate,
+                        self.sample_rate as f32,
+                    )),
+                    _ => None,
+                });
+                let mut osc_noise_out = 0.0;
+                for (step_l, step_r) in izip!(oversampled_l.iter_mut(), oversampled_r.iter_mut()).take(factor) {
+                    let mut out = 0.0;
+                    let mut out_stereo = 0.0;
+                    // Oscillators run in reverse, so each one's output is either from this sample
+                    // or, if it hasn't run yet, the previous one
+                    for (i, osc) in self.oscillators.iter().enumerate().rev() {
+                        let smoothed = &oscillators[i];
+                        let depth = velocity_osc_mod[i] * osc_lfo_mod[i];
+                        let modulators = voice.modulators(i);
+                        let received = self.modulation.received(sample_index, i, &modulators, &osc_delta, depth);
+                        // Like any modulator, feedback is the sum of every unison voice, so each
+                        // voice hears the others as well as itself
+                        let feedback = modulators[i] * smoothed.feedback * depth * FEEDBACK_DEPTH;
+                        let master = (i + 1) % 3;
+                        let mod_osc_out = voice.osc_outputs[master];
+                        let pwm = smoothed.pwm_osc * depth;
+                        let delta = modulate_delta(osc_delta[i], osc.fm_mode, received.fm, received.fm_octaves);
+
+                        let noise = osc_noise[i];
+                        let unison_amp = if noise.is_some() { 1.0 } else { osc.unison_amp };
+
+                        // Oscillators sync to the one after them, which has already been advanced,
+                        // except Osc 3, which syncs to Osc 1's previous step
+                        let sync = voice.osc_voice[master]
+                            .sync_source(osc.sync, self.oscillators[master].voice_count.into());
+                        voice.osc_voice[i].add_phase(
+                            delta,
                             osc.voice_count.into(),
-                            mod_osc_out * osc.pm * 150.0,
+                            osc.voices_detune,
+                            Some(&sync),
                         );
+                        let pulse_width = (osc_pulse_width[i] + mod_osc_out * pwm * PI).clamp(0.0, 2.0 * PI);
+                        let smoothed = &SmoothedOscillator { pulse_width, ..*smoothed };
+                        let lfo_pwm = lfo_controls.pwm_mod != 0.0
+                            && self.lfo_params.target_osc.is_none_or(|x| x == i);
+                        let blep = osc.blep_shape(smoothed, lfo_pwm);
+
+                        // Pulses and skewed triangles are made by subtracting a shifted copy of a
+                        // simpler wave, unless they're made with bandlimited steps
+                        let difference = match blep {
+                            Some(_) => None,
+                            None => osc.wave_difference(smoothed, pulse_width),
+                        };
+                        let (wave, offset) = match &difference {
+                            Some(difference) => (&difference.wave, difference.offsets.0),
+                            None => (&osc.wave, 0.0),
+                        };
+                        let pm_offset = received.pm + feedback;
+
+                        if let Some(width) = self.stereo_width {
+                            let (mut osc_out_l, mut osc_out_r) = match (noise, blep) {
+                                (Some(noise), _) => (noise, noise),
+                                (None, Some(shape)) => voice.osc_voice[i].generate_blep_stereo(
+                                    shape,
+                                    osc.voice_count.into(),
+                                    pm_offset,
+                                ),
+                                (None, None) => self.waves.generator(osc.engine, wave, osc_position[i], osc.wavetable_morph)
+                                    .generate_multi_stereo_pm(
+                                    osc_delta[i],
+                                    oscillator_rate,
+                                    &voice.osc_voice[i].voice_phases,
+                                    osc.voice_count.into(),
+                                    pm_offset + offset,
+                                ),
+                            };
     
-                        // if pulse wave, subtract 2 saw waves
-                        if let OscWave::Pulse { width } = osc.wave {
-                            let pulse = self.waves.select(&osc.wave).delta_index(osc_delta[i], self.sample_rate as f32)
-                            .generate_multi_stereo_pm(phases, osc.voice_count.into(), width);
-                            osc_out_l -= pulse.0;
-                            osc_out_r -= pulse.1;
-                        }
+                            if let Some(difference) = &difference {
+                                let shifted = self.waves.generator(osc.engine, wave, 0.0, osc.wavetable_morph)
+                                .generate_multi_stereo_pm(osc_delta[i], oscillator_rate, &voice.osc_voice[i].voice_phases, osc.voice_count.into(), pm_offset + difference.offsets.1);
+                                osc_out_l = (osc_out_l - shifted.0) * difference.scale;
+                                osc_out_r = (osc_out_r - shifted.1) * difference.scale;
+                            }
     
-                        out += osc_out_l * osc.unison_amp * osc.amp * lerp(1.0, (mod_osc_out + 1.0) / 2.0, osc.am) * osc_lfo_amp[i];
-                        out_stereo += osc_out_r * osc.unison_amp * osc.amp * lerp(1.0, (mod_osc_out + 1.0) / 2.0, osc.am) * osc_lfo_amp[i];
+                            let gain = unison_amp * smoothed.amp * received.am * osc_lfo_amp[i];
+                            if noise.is_some() {
+                                osc_noise_out += osc_out_l * gain / factor as f32;
+                            } else {
+                                out += osc_out_l * gain;
+                                out_stereo += osc_out_r * gain;
+                            }
     
-                        osc_out_l + osc_out_r
-                    } else {
-                        let mut osc_out = self.waves.select(&osc.wave).delta_index(osc_delta[i], self.sample_rate as f32)
-                            .generate_multi_pm(
-                            &phases,
-                            osc.voice_count.into(),
-                            mod_osc_out * osc.pm * 150.0,
-                        );
+                            voice.set_osc_output(i, osc_out_l + osc_out_r);
+                            voice.ring_inputs[i] = (osc_out_l + osc_out_r) * unison_amp;
+                        } else {
+                            let mut osc_out = match (noise, blep) {
+                                (Some(noise), _) => noise,
+                                (None, Some(shape)) => voice.osc_voice[i]
+                                    .generate_blep(shape, osc.voice_count.into(), pm_offset)
+                                    .sum(),
+                                (None, None) => self.waves.generator(osc.engine, wave, osc_position[i], osc.wavetable_morph)
+                                    .generate_multi_pm(
+                                    osc_delta[i],
+                                    oscillator_rate,
+                                    &voice.osc_voice[i].voice_phases,
+                                    osc.voice_count.into(),
+                                    pm_offset + offset,
+                                ),
+                            };
     
-                        // if pulse wave, subtract 2 saw waves
-                        if let OscWave::Pulse { width } = osc.wave {
-                            osc_out -= self.waves.select(&osc.wave).delta_index(osc_delta[i], self.sample_rate as f32)
-                            .generate_multi_pm(phases, osc.voice_count.into(), width);
-                        }
+                            if let Some(difference) = &difference {
+                                let shifted = self.waves.generator(osc.engine, wave, 0.0, osc.wavetable_morph)
+                                .generate_multi_pm(osc_delta[i], oscillator_rate, &voice.osc_voice[i].voice_phases, osc.voice_count.into(), pm_offset + difference.offsets.1);
+                                osc_out = (osc_out - shifted) * difference.scale;
+                            }
     
-                        out += osc_out * osc.unison_amp * osc.amp * lerp(1.0, (mod_osc_out + 1.0) / 2.0, osc.am) * osc_lfo_amp[i];
+                            let gain = unison_amp * smoothed.amp * received.am * osc_lfo_amp[i];
+                            if noise.is_some() {
+                                osc_noise_out += osc_out * gain / factor as f32;
+                            } else {
+                                out += osc_out * gain;
+                            }
     
-                        osc_out * osc_lfo_mod[i]
+                            voice.set_osc_output(i, osc_out);
+                            voice.ring_inputs[i] = osc_out * unison_amp;
+                        }
                     }
-                });
+
+                    let ring = self.ring.output(sample_index, &voice.ring_inputs);
+                    out += ring;
+                    out_stereo += ring;
+
+                    *step_l = out;
+                    *step_r = out_stereo;
+                }
+                let (mut out, mut out_stereo) = if factor > 1 {
+                    (
+                        voice.decimators[0].process(&self.decimation_filter, &oversampled_l[..factor]),
+                        voice.decimators[1].process(&self.decimation_filter, &oversampled_r[..factor]),
+                    )
+                } else {
+                    (oversampled_l[0], oversampled_r[0])
+                };
+
+                // Dedicated noise generator, which goes through the filter like the oscillators
+                let noise = voice.noise.next(
+                    self.noise.noise_type,
+                    self.noise.rate,
+                    self.sample_rate as f32,
+                ) * self.noise.smoothed_level(sample_index);
+                let noise = voice
+                    .noise_delay
+                    .process(osc_noise_out + noise, decimation_delay);
+                out += noise;
+                out_stereo += noise;
 
                 // Update filter controls
+                filter_controls.resonance =
+                    (filter_controls.resonance + self.mseg.resonance_offset(mseg)).clamp(0.1, 10.0);
+                let cutoff_inputs = filter::CutoffInputs {
+                    note: voice.id as f32 + voice.semitone_detune,
+                    frequency: delta * self.sample_rate as f32 / (2.0 * PI),
+                    velocity,
+                    mod_mult: (1.0 + lfo * lfo_controls.filter_mod) * self.mseg.cutoff_mult(mseg) * velocity_cutoff,
+                    envelope: filter_envelope,
+                };
                 let cutoff = self.filter_controller.get_cutoff(
-                    keytrack_freq + keytrack_freq * lfo * self.lfo_params.filter_mod,
-                    envelope_index,
-                    voice.release_time,
+                    &filter_controls,
+                    &cutoff_inputs,
                     self.sample_rate as f32,
                 );
                 
                 voice.filter.set(
                     self.filter_controller.filter_model,
                     cutoff,
-                    self.filter_controller.resonance,
+                    filter_controls.resonance,
                     self.sample_rate as f32,
                     self.filter_controller.filter_type,
                 );
@@ -298,41 +498,31 @@ impl ThreeOsc {
                 voice.filter.set_params(
                     self.sample_rate as f32,
                     cutoff,
-                    self.filter_controller.resonance,
+                    filter_controls.resonance,
                 );
 
-                out = voice.filter.process(out * drive);
+                out = self.filter_controller.process(
+                    &mut voice.filter,
+                    &mut voice.dc_blocker,
+                    out,
+                    filter_controls.drive,
+                    self.sample_rate as f32,
+                );
 
                 if let Some(width) = self.stereo_width {
-                    // amplitude envelope
-                    let envelope = if let Some(release_time) = voice.release_time {
-                        let release_index = release_time as f32 / self.sample_rate as f32;
-                        self
-                            .gain_envelope
-                            .sample_released(release_index, envelope_index)
-                    } else {
-                        self.gain_envelope.sample_held(envelope_index)
-                    };
-    
-                    // keyboard velocity scaling
-                    out *= velocity;
-                    out_stereo *= velocity;
+                    // keyboard velocity and key scaling
+                    out *= velocity_amp * key_amp * self.mseg.volume_mult(mseg);
+                    out_stereo *= velocity_amp * key_amp * self.mseg.volume_mult(mseg);
     
-                    *out_l += out * envelope;
-                    *out_r += out_stereo * envelope;
+                    // amplitude envelope
+                    *out_l += out * gain_envelope;
+                    *out_r += out_stereo * gain_envelope;
                 } else {
                     // amplitude envelope
-                    out = if let Some(release_time) = voice.release_time {
-                        let release_index = release_time as f32 / self.sample_rate as f32;
-                        out * self
-                            .gain_envelope
-                            .sample_released(release_index, envelope_index)
-                    } else {
-                        out * self.gain_envelope.sample_held(envelope_index)
-                    };
+                    out *= gain_envelope;
     
-                    // keyboard velocity scaling
-                    out *= velocity;
+                    // keyboard velocity and key scaling
+                    out *= velocity_amp * key_amp * self.mseg.volume_mult(mseg);
     
                     *out_l += out;
                     *out_r += out;
@@ -340,9 +530,10 @@ impl ThreeOsc {
             }
         }
         // Apply output volume
-        for (out_l, out_r) in izip!(output_left, output_right) {
-            *out_l *= self.output_volume;
-            *out_r *= self.output_volume;
+        for (i, (out_l, out_r)) in izip!(output_left, output_right).enumerate() {
+            let output_volume = self.output_volume_smoother.at(i);
+            *out_l *= output_volume;
+            *out_r *= output_volume;
         }
     }
     pub fn pitch_bend(&mut self, bend: u16) {
@@ -355,6 +546,7 @@ impl ThreeOsc {
 
 mod notes;
 
+#[derive(Debug, Clone, Copy)]
 pub enum Polyphony {
     Polyphonic,
     Monophonic,
@@ -365,11 +557,27 @@ pub enum Polyphony {
 // TODO: Separate phase, oscillator, and filter from note data.
 pub struct Voice {
     id: u32,
-    runtime: u32,
-    release_time: Option<u32>,
+    released: bool,
+    gain_envelope: EnvelopeVoice,
+    filter_envelope: EnvelopeVoice,
     osc_voice: [SuperVoice; 3],
+    /// Latest output of each oscillator, for modulating the others.
+    osc_outputs: [f32; 3],
+    /// Output of each oscillator the sample before `osc_outputs`, for feedback.
+    osc_previous_outputs: [f32; 3],
+    /// Latest output of each oscillator at the level it's mixed at, before its amp, for the
+    /// ring modulator. Unlike `osc_outputs`, unison doesn't make this louder.
+    ring_inputs: [f32; 3],
     lfo: OscVoice,
     filter: filter::FilterContainer,
+    dc_blocker: DcBlocker,
+    mseg: MsegVoice,
+    osc_noise: [NoiseVoice; 3],
+    noise: NoiseVoice,
+    /// Decimators for the left (or mono) and right oscillator outputs.
+    decimators: [Decimator; 2],
+    /// Delays the noise, which isn't decimated, by as much as the decimators delay the oscillators.
+    noise_delay: Delay,
     velocity: u8,
     pitch_multiply: f32,
     semitone_detune: f32,
@@ -384,23 +592,53 @@ impl Voice {
 
         Self {
             id: index.into(),
-            runtime: 0,
-            release_time: None,
+            released: false,
+            gain_envelope: Default::default(),
+            filter_envelope: Default::default(),
             osc_voice,
+            osc_outputs: [0.0; 3],
+            osc_previous_outputs: [0.0; 3],
+            ring_inputs: [0.0; 3],
             lfo: Default::default(),
             velocity,
             filter: filter::FilterContainer::None,
+            dc_blocker: Default::default(),
+            mseg: Default::default(),
+            osc_noise: Default::default(),
+            noise: Default::default(),
+            decimators: Default::default(),
+            noise_delay: Default::default(),
             pitch_multiply: 1.0,
             semitone_detune: 0.0,
         }
     }
-    pub fn release(&mut self) {
-        if self.release_time.is_none() {
-            self.release_time = Some(self.runtime)
+    pub fn release(&mut self, mseg: &Mseg) {
+        if !self.released {
+            self.released = true;
+            self.gain_envelope.release();
+            self.filter_envelope.release();
+            self.mseg.release(mseg);
         }
     }
-    pub fn advance(&mut self) {
-        self.runtime += 1;
+    /// Starts the note's envelopes again from their current levels.
+    pub fn retrigger(&mut self) {
+        self.released = false;
+        self.gain_envelope.retrigger();
+        self.filter_envelope.retrigger();
+        self.mseg.restart();
+    }
+    /// Outputs of each oscillator as heard by oscillator `index` when it modulates it.
+    ///
+    /// An oscillator hears itself averaged over its last two samples, which stops strong
+    /// feedback from flipping between two values every sample.
+    fn modulators(&self, index: usize) -> [f32; 3] {
+        let mut outputs = self.osc_outputs;
+        outputs[index] = (self.osc_outputs[index] + self.osc_previous_outputs[index]) / 2.0;
+        outputs
+    }
+    fn set_osc_output(&mut self, index: usize, output: f32) {
+        self.osc_previous_outputs[index] = self.osc_outputs[index];
+        self.osc_outputs[index] = output;
     }
     pub fn delta(&self, sample_rate: f32) -> f32 {
         2.0 * PI
@@ -428,71 +666,7 @@ impl Voice {
 
 pub mod oscillator;
 
-mod envelopes {
-    #[derive(Debug, Clone)]
-    pub struct AdsrEnvelope {
-        pub attack_time: f32,
-        pub decay_time: f32,
-        pub release_time: f32,
-        pub sustain_level: f32,
-        pub slope: f32,
-        pub attack_slope: f32,
-    }
-    impl AdsrEnvelope {
-        pub fn new(
-            attack_time: f32,
-            decay_time: f32,
-            release_time: f32,
-            sustain_level: f32,
-            slope: f32,
-        ) -> Self {
-            Self {
-                attack_time,
-                decay_time,
-                release_time,
-                sustain_level,
-                slope: Self::slope(slope),
-                attack_slope: Self::slope(-slope),
-            }
-        }
-        /// 0 is linear, positive is biased towards zero, negative is biased towards max.
-        fn slope(slope: f32) -> f32 {
-            2.0_f32.powf(slope)
-        }
-        pub fn set_slope(&mut self, slope: f32) {
-            // The inverted / negative slope flares up too quickly compared to the
-            // positive slope, so we divide the negative slope by an arbitrary number
-            if slope.is_sign_positive() {
-                self.slope = Self::slope(slope);
-                self.attack_slope = Self::slope(-slope / 4.0);
-            } else {
-                self.slope = Self::slope(slope / 4.0);
-                self.attack_slope = Self::slope(-slope);
-            }
-        }
-        /// Returns the envelope CV (between 0.0 and 1.0) associated with the given index
-        pub fn sample_held(&self, index: f32) -> f32 {
-            if index <= self.attack_time {
-                (index / self.attack_time).powf(self.attack_slope)
-            } else if index - self.attack_time <= self.decay_time {
-                (1.0 - (index - self.attack_time) / self.decay_time).powf(self.slope)
-                    * (1.0 - self.sustain_level)
-                    + self.sustain_level
-            } else {
-                self.sustain_level
-            }
-        }
-        pub fn sample_released(&self, release_index: f32, index: f32) -> f32 {
-            assert!(release_index <= index);
-            if index - release_index > self.release_time {
-                0.0
-            } else {
-                let level = self.sample_held(release_index);
-                (1.0 - (index - release_index) / self.release_time).powf(self.slope) * level
-            }
-        }
-    }
-}
+mod envelopes;
 
 pub struct LfoParams {
     pub freq: f32,
@@ -501,12 +675,56 @@ pub struct LfoParams {
     pub amp_mod: f32,
     pub mod_mod: f32,
     pub filter_mod: f32,
+    pub position_mod: f32,
+    pub pwm_mod: f32,
     pub target_osc: Option<usize>,
+    smoothers: LfoSmoothers,
 }
 impl LfoParams {
     fn delta(&self, sample_rate: f32) -> f32 {
         2.0 * PI * self.freq / sample_rate
     }
+    /// Moves the smoothed copies of each modulation amount towards their current values.
+    fn update_smoothing(&mut self, block: &SmoothingBlock) {
+        self.smoothers.freq_mod.next_block(self.freq_mod, block);
+        self.smoothers.amp_mod.next_block(self.amp_mod, block);
+        self.smoothers.mod_mod.next_block(self.mod_mod, block);
+        self.smoothers.filter_mod.next_block(self.filter_mod, block);
+        self.smoothers.position_mod.next_block(self.position_mod, block);
+        self.smoothers.pwm_mod.next_block(self.pwm_mod, block);
+    }
+    /// Returns the smoothed modulation amounts `index` samples into the current buffer.
+    #[inline]
+    fn smoothed(&self, index: usize) -> SmoothedLfo {
+        SmoothedLfo {
+            freq_mod: self.smoothers.freq_mod.at(index),
+            amp_mod: self.smoothers.amp_mod.at(index),
+            mod_mod: self.smoothers.mod_mod.at(index),
+            filter_mod: self.smoothers.filter_mod.at(index),
+            position_mod: self.smoothers.position_mod.at(index),
+            pwm_mod: self.smoothers.pwm_mod.at(index),
+        }
+    }
+}
+
+#[derive(Default)]
+struct LfoSmoothers {
+    freq_mod: Smoother,
+    amp_mod: Smoother,
+    mod_mod: Smoother,
+    filter_mod: Smoother,
+    position_mod: Smoother,
+    pwm_mod: Smoother,
+}
+
+/// Snapshot of the LFO's smoothed modulation amounts at a single sample.
+struct SmoothedLfo {
+    freq_mod: f32,
+    amp_mod: f32,
+    mod_mod: f32,
+    filter_mod: f32,
+    position_mod: f32,
+    pwm_mod: f32,
 }
 impl Default for LfoParams {
     fn default() -> Self {
@@ -517,14 +735,205 @@ impl Default for LfoParams {
             amp_mod: 0.0,
             mod_mod: 0.0,
             filter_mod: 0.0,
+            position_mod: 0.0,
+            pwm_mod: 0.0,
             target_osc: None,
+            smoothers: Default::default(),
         }
     }
 }
 
 pub(crate) mod filter;
 
+pub mod smoothing;
+
+pub mod mseg;
+
+pub mod velocity;
+
+pub mod key_scaling;
+
+pub mod noise;
+
+pub mod harmonics;
+
+pub mod algorithm;
+
+pub mod oversampling;
+
+pub mod ring;
+
 #[inline]
 fn lerp(from: f32, to: f32, amount: f32) -> f32 {
     (to - from).mul_add(amount, from)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feedback_hears_its_last_two_samples() {
+        let mut voice = Voice::from_midi_note(60, 100, &<[OscillatorParams; 3]>::default());
+        voice.set_osc_output(0, 1.0);
+        voice.set_osc_output(0, 0.5);
+        voice.set_osc_output(1, 0.25);
+        assert_eq!(voice.modulators(0), [0.75, 0.25, 0.0]);
+        assert_eq!(voice.modulators(1), [0.5, 0.125, 0.0]);
+    }
+
+    #[test]
+    fn feedback_bends_a_sine_without_chattering() {
+        let render = |feedback| {
+            let mut synth = ThreeOsc::new(48000.0);
+            synth.filter_controller.filter_model = filter::FilterModel::None;
+            synth.oscillators[0].feedback = feedback;
+            for osc in synth.oscillators.iter_mut() {
+                osc.phase_rand = 0.0;
+            }
+            synth.note_on(69, 127);
+            let mut left = [0.0; 4800];
+            let mut right = [0.0; 4800];
+            synth.run(&mut left, &mut right);
+            left
+        };
+        // Number of times the wave changes direction
+        let turns = |samples: &[f32]| {
+            samples
+                .windows(3)
+                .filter(|x| (x[1] - x[0]) * (x[2] - x[1]) < 0.0)
+                .count()
+        };
+        let sine = render(0.0);
+        let bent = render(1.0);
+        assert!(sine.iter().zip(bent.iter()).any(|(a, b)| (a - b).abs() > 0.01));
+        // A saw-like wave can ripple once a cycle, but without averaging, strong feedback
+        // flips direction every sample or two
+        assert!(turns(&bent) <= turns(&sine) * 4);
+    }
+
+    #[test]
+    fn oversampling_keeps_pitch_and_level() {
+        let render = |oversampling, wave| {
+            let mut synth = ThreeOsc::new(48000.0);
+            synth.filter_controller.filter_model = filter::FilterModel::None;
+            for osc in synth.oscillators.iter_mut() {
+                osc.phase_rand = 0.0;
+            }
+            synth.oscillators[0].wave = wave;
+            synth.oscillators[1].amp = 0.0;
+            synth.oscillators[2].amp = 0.0;
+            synth.oversampling = oversampling;
+            // Every render gets the same noise
+            fastrand::seed(1);
+            synth.note_on(69, 127);
+            let mut left = [0.0; 4800];
+            let mut right = [0.0; 4800];
+            synth.run(&mut left, &mut right);
+            left
+        };
+        // Only the second half is compared, after the decimator has stopped ringing from the
+        // note starting
+        let crossings = |samples: &[f32]| {
+            samples[2400..]
+                .windows(2)
+                .filter(|x| x[0] * x[1] < 0.0)
+                .count()
+        };
+        let peak = |samples: &[f32]| {
+            samples[2400..]
+                .iter()
+                .fold(0.0_f32, |peak, x| peak.max(x.abs()))
+        };
+        let rms = |samples: &[f32]| {
+            let settled = &samples[2400..];
+            (settled.iter().map(|x| x * x).sum::<f32>() / settled.len() as f32).sqrt()
+        };
+        let plain = render(Oversampling::Off, OscWave::Sine);
+        for oversampling in [Oversampling::X2, Oversampling::X4, Oversampling::X8] {
+            let oversampled = render(oversampling, OscWave::Sine);
+            let crossings_moved = crossings(&plain).abs_diff(crossings(&oversampled));
+            assert!(crossings_moved <= 1, "{oversampling:?}");
+            let level = peak(&oversampled) / peak(&plain);
+            assert!((level - 1.0).abs() < 0.01, "{oversampling:?}");
+        }
+        // Noise is made at the sample rate whatever the oversampling, so it shouldn't change
+        for noise in [
+            noise::NoiseType::White,
+            noise::NoiseType::Pink,
+            noise::NoiseType::Brown,
+            noise::NoiseType::Digital,
+        ] {
+            let wave = OscWave::Noise(noise);
+            let plain = rms(&render(Oversampling::Off, wave.clone()));
+            for oversampling in [Oversampling::X2, Oversampling::X4, Oversampling::X8] {
+                let level = rms(&render(oversampling, wave.clone())) / plain;
+                assert!((level - 1.0).abs() < 0.01, "{noise:?} {oversampling:?}: {level}");
+            }
+        }
+    }
+
+    #[test]
+    fn ring_modulator_level_ignores_unison() {
+        let render = |voices| {
+            let mut synth = ThreeOsc::new(48000.0);
+            synth.filter_controller.filter_model = filter::FilterModel::None;
+            for osc in synth.oscillators.iter_mut() {
+                osc.amp = 0.0;
+                osc.voice_count = voices;
+                osc.update_unison_amp();
+            }
+            synth.ring.level = 1.0;
+            fastrand::seed(1);
+            synth.note_on(69, 127);
+            let mut left = [0.0; 4800];
+            let mut right = [0.0; 4800];
+            synth.run(&mut left, &mut right);
+            (left.iter().map(|x| x * x).sum::<f32>() / left.len() as f32).sqrt()
+        };
+        let single = render(1);
+        for voices in [2, 8, 32] {
+            let level = render(voices) / single;
+            assert!((0.5..2.0).contains(&level), "{voices} voices: {level}");
+        }
+    }
+
+    #[test]
+    fn envelopes_never_jump_in_any_polyphony_mode() {
+        for polyphony in [
+            Polyphony::Polyphonic,
+            Polyphony::Monophonic,
+            Polyphony::Legato,
+        ] {
+            let mut synth = ThreeOsc::new(48000.0);
+            synth.polyphony = polyphony;
+            synth.gain_envelope = DahdsrEnvelope::new(0.01, 0.1, 0.05, 0.5, 0.0);
+
+            let mut last = 0.0;
+            for i in 0..12000 {
+                // Retrigger and release in the middle of each stage
+                match i {
+                    0 => synth.note_on(60, 100),
+                    100 => synth.note_on(64, 100),
+                    3000 => synth.note_off(64, 0),
+                    6000 => synth.note_off(60, 0),
+                    6200 => synth.note_on(67, 100),
+                    9000 => synth.note_off(67, 0),
+                    _ => {}
+                }
+                synth.run(&mut [0.0], &mut [0.0]);
+                let level: f32 = synth
+                    .voices
+                    .iter()
+                    .map(|voice| voice.gain_envelope.level())
+                    .sum();
+                assert!(
+                    (level - last).abs() < 0.01,
+                    "{polyphony:?} envelope jumped from {last} to {level} at sample {i}"
+                );
+                last = level;
+            }
+            assert!(synth.voices.is_empty());
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use super::lerp;
+use super::smoothing::{Smoother, SmoothingBlock};
+
+/// Phase offset in radians given by a modulator at 1.0 with a PM amount of 1.0.
+const PM_DEPTH: f32 = 150.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Which oscillators modulate which, like the algorithms of FM synths.
+///
+/// Osc 3 runs first and Osc 1 last, so an oscillator modulated by an oscillator before it
+/// hears that oscillator's previous sample. An oscillator modulating itself is feedback,
+/// which every algorithm has through each oscillator's own feedback amount.
+pub enum Algorithm {
+    /// Osc 3 modulates Osc 2, which modulates Osc 1.
+    Stack,
+    /// No modulation, so the oscillators are just mixed.
+    Parallel,
+    /// Osc 2 and Osc 3 each modulate Osc 1.
+    Branch,
+    /// Osc 3 modulates Osc 2, and both modulate Osc 1.
+    Sum,
+    /// Osc 1 modulates Osc 2, which modulates Osc 3.
+    Reverse,
+    /// Osc 1 and Osc 2 modulate each other.
+    Cross,
+    /// Each oscillator modulates the one before it, and Osc 1 modulates Osc 3.
+    Loop,
+}
+impl Algorithm {
+    pub fn from_index(index: f32) -> Self {
+        match index {
+            x if x < 1.0 => Algorithm::Stack,
+            x if x < 2.0 => Algorithm::Parallel,
+            x if x < 3.0 => Algorithm::Branch,
+            x if x < 4.0 => Algorithm::Sum,
+            x if x < 5.0 => Algorithm::Reverse,
+            x if x < 6.0 => Algorithm::Cross,
+            _ => Algorithm::Loop,
+        }
+    }
+    /// Connections between oscillators, as `(source, destination)` indices.
+    pub fn connections(&self) -> &'static [(usize, usize)] {
+        match self {
+            Algorithm::Stack => &[(2, 1), (1, 0)],
+            Algorithm::Parallel => &[],
+            Algorithm::Branch => &[(2, 0), (1, 0)],
+            Algorithm::Sum => &[(2, 1), (2, 0), (1, 0)],
+            Algorithm::Reverse => &[(0, 1), (1, 2)],
+            Algorithm::Cross => &[(0, 1), (1, 0)],
+            Algorithm::Loop => &[(2, 1), (1, 0), (0, 2)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+/// How strongly a connection modulates its destination.
+pub struct ModAmounts {
+    pub pm: f32,
+    /// Modulation index for linear FM, i.e. the peak frequency deviation from a sine
+    /// modulator divided by the modulator's frequency. Exponential FM uses it as octaves.
+    pub fm: f32,
+    /// Unipolar amplitude modulation, which fades between the destination and the
+    /// destination scaled by the modulator from 0 to 1.
+    pub am: f32,
+    /// Ring modulation, which fades between the destination and the destination multiplied
+    /// by the modulator, so the modulator's negative half flips the destination over.
+    pub rm: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Modulation an oscillator receives at a single sample, from all of its connections.
+pub struct ReceivedModulation {
+    /// Phase offset, in radians.
+    pub pm: f32,
+    /// Frequency deviation for linear FM, in radians per sample.
+    pub fm: f32,
+    /// Pitch deviation for exponential FM, in octaves.
+    pub fm_octaves: f32,
+    /// Gain from amplitude and ring modulation, which can be negative.
+    pub am: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+struct AmountSmoothers {
+    pm: Smoother,
+    fm: Smoother,
+    am: Smoother,
+    rm: Smoother,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModulationParams {
+    pub algorithm: Algorithm,
+    /// Amounts of each possible connection, indexed by `[destination][source]`. Only the
+    /// connections in the algorithm are used.
+    pub amounts: [[ModAmounts; 3]; 3],
+    smoothers: [[AmountSmoothers; 3]; 3],
+}
+impl ModulationParams {
+    /// Moves the smoothed copies of every amount towards their current values, including
+    /// unused connections so they don't jump when the algorithm changes.
+    pub fn update_smoothing(&mut self, block: &SmoothingBlock) {
+        for (amounts, smoothers) in self.amounts.iter().zip(self.smoothers.iter_mut()) {
+            for (amount, smoother) in amounts.iter().zip(smoothers.iter_mut()) {
+                smoother.pm.next_block(amount.pm, block);
+                smoother.fm.next_block(amount.fm, block);
+                smoother.am.next_block(amount.am, block);
+                smoother.rm.next_block(amount.rm, block);
+            }
+        }
+    }
+    /// Sums the modulation oscillator `destination` receives `index` samples into the current
+    /// buffer, given each oscillator's latest output and phase increment. `depth` scales every
+    /// amount, e.g. for velocity.
+    pub fn received(
+        &self,
+        index: usize,
+        destination: usize,
+        outputs: &[f32; 3],
+        deltas: &[f32; 3],
+        depth: f32,
+    ) -> ReceivedModulation {
+        let mut received = ReceivedModulation {
+            pm: 0.0,
+            fm: 0.0,
+            fm_octaves: 0.0,
+            am: 1.0,
+        };
+        for &(source, _) in self
+            .algorithm
+            .connections()
+            .iter()
+            .filter(|(_, x)| *x == destination)
+        {
+            let smoothers = &self.smoothers[destination][source];
+            let signal = outputs[source];
+            received.pm += signal * smoothers.pm.at(index) * depth * PM_DEPTH;
+            let fm = signal * smoothers.fm.at(index) * depth;
+            received.fm += fm * deltas[source];
+            received.fm_octaves += fm;
+            received.am *= lerp(1.0, (signal + 1.0) / 2.0, smoothers.am.at(index) * depth);
+            received.am *= lerp(1.0, signal, smoothers.rm.at(index) * depth);
+        }
+        received
+    }
+}
+impl Default for ModulationParams {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::Stack,
+            amounts: Default::default(),
+            smoothers: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::smoothing::SmoothingParams;
+
+    fn params(algorithm: Algorithm) -> ModulationParams {
+        let mut params = ModulationParams {
+            algorithm,
+            amounts: [[ModAmounts {
+                pm: 1.0,
+                fm: 0.5,
+                am: 1.0,
+                rm: 0.0,
+            }; 3]; 3],
+            ..Default::default()
+        };
+        params.update_smoothing(&SmoothingParams::default().block(64, 48000.0));
+        params
+    }
+
+    #[test]
+    fn only_connected_oscillators_are_heard() {
+        let outputs = [0.25, 0.5, -1.0];
+        let deltas = [0.1; 3];
+        let stack = params(Algorithm::Stack);
+        let received = stack.received(0, 0, &outputs, &deltas, 1.0);
+        assert_eq!(received.pm, 0.5 * PM_DEPTH);
+        assert_eq!(received.fm_octaves, 0.25);
+        assert_eq!(received.am, 0.75);
+        // Nothing modulates the top of the stack
+        assert_eq!(stack.received(0, 2, &outputs, &deltas, 1.0).pm, 0.0);
+
+        let received = params(Algorithm::Parallel).received(0, 0, &outputs, &deltas, 1.0);
+        assert_eq!(received.pm, 0.0);
+        assert_eq!(received.am, 1.0);
+
+        // Osc 3 can be modulated too, from either side
+        let received = params(Algorithm::Reverse).received(0, 2, &outputs, &deltas, 1.0);
+        assert_eq!(received.pm, 0.5 * PM_DEPTH);
+        let received = params(Algorithm::Loop).received(0, 2, &outputs, &deltas, 1.0);
+        assert_eq!(received.pm, 0.25 * PM_DEPTH);
+        assert_eq!(params(Algorithm::Reverse).received(0, 0, &outputs, &deltas, 1.0).pm, 0.0);
+    }
+
+    #[test]
+    fn connections_to_one_oscillator_add_up() {
+        let outputs = [0.0, 0.5, 0.25];
+        let received = params(Algorithm::Branch).received(0, 0, &outputs, &[0.1; 3], 0.5);
+        assert_eq!(received.pm, 0.75 * 0.5 * PM_DEPTH);
+        assert_eq!(received.am, lerp(1.0, 0.75, 0.5) * lerp(1.0, 0.625, 0.5));
+    }
+
+    #[test]
+    fn ring_modulation_is_bipolar() {
+        let mut params = ModulationParams::default();
+        params.amounts[0][1].rm = 1.0;
+        params.update_smoothing(&SmoothingParams::default().block(64, 48000.0));
+        // Full ring modulation multiplies by the modulator, flipping on its negative half
+        for signal in [1.0, 0.5, -0.5, -1.0] {
+            let received = params.received(0, 0, &[0.0, signal, 0.0], &[0.1; 3], 1.0);
+            assert_eq!(received.am, signal);
+        }
+        // Half way, a silent modulator only halves the destination
+        let received = params.received(0, 0, &[0.0; 3], &[0.1; 3], 0.5);
+        assert_eq!(received.am, 0.5);
+    }
+
+    #[test]
+    fn linear_fm_deviation_follows_the_modulation_index() {
+        let mut params = ModulationParams::default();
+        params.amounts[0][1].fm = 3.0;
+        params.update_smoothing(&SmoothingParams::default().block(64, 48000.0));
+        // A sine modulator at its peak, an octave below the carrier
+        let received = params.received(0, 0, &[0.0, 1.0, 0.0], &[0.2, 0.1, 0.0], 1.0);
+        assert!((received.fm - 3.0 * 0.1).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,288 @@
+use super::lerp;
+
+#[derive(Debug, Clone)]
+/// Delay, attack, hold, decay, sustain, release envelope.
+///
+/// All times are in seconds. Each curved segment has its own curve, where 0 is linear,
+/// positive values make the segment change quickly at first (punchier), and negative values
+/// make it change slowly at first.
+pub struct DahdsrEnvelope {
+    pub delay_time: f32,
+    pub attack_time: f32,
+    pub hold_time: f32,
+    pub decay_time: f32,
+    pub sustain_level: f32,
+    /// Change in level per second once the sustain stage is reached, so held notes can
+    /// keep fading out (negative) or swell back up (positive).
+    pub sustain_slope: f32,
+    pub release_time: f32,
+    attack_exponent: f32,
+    decay_exponent: f32,
+    release_exponent: f32,
+}
+impl DahdsrEnvelope {
+    pub fn new(
+        attack_time: f32,
+        decay_time: f32,
+        release_time: f32,
+        sustain_level: f32,
+        curve: f32,
+    ) -> Self {
+        let mut envelope = Self {
+            delay_time: 0.0,
+            attack_time,
+            hold_time: 0.0,
+            decay_time,
+            sustain_level,
+            sustain_slope: 0.0,
+            release_time,
+            attack_exponent: 1.0,
+            decay_exponent: 1.0,
+            release_exponent: 1.0,
+        };
+        envelope.set_attack_curve(curve);
+        envelope.set_decay_curve(curve);
+        envelope.set_release_curve(curve);
+        envelope
+    }
+    pub fn set_attack_curve(&mut self, curve: f32) {
+        // A punchy attack rises quickly, so its exponent is below 1, unlike the other stages.
+        self.attack_exponent = 2.0_f32.powf(-curve);
+    }
+    pub fn set_decay_curve(&mut self, curve: f32) {
+        self.decay_exponent = 2.0_f32.powf(curve);
+    }
+    pub fn set_release_curve(&mut self, curve: f32) {
+        self.release_exponent = 2.0_f32.powf(curve);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Delay,
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+    Finished,
+}
+
+#[derive(Debug, Clone)]
+/// Per-voice position in a `DahdsrEnvelope`.
+///
+/// The envelope's output is tracked between samples, so attacks and releases always start
+/// from the current level. This means retriggering a note or releasing it mid-attack never
+/// jumps (and clicks).
+pub struct EnvelopeVoice {
+    stage: Stage,
+    /// Time spent in the current stage, in seconds.
+    elapsed: f32,
+    /// Level when the current stage began.
+    start_level: f32,
+    level: f32,
+    /// Multiplier for this voice's attack time, e.g. for velocity sensitive attacks.
+    attack_scale: f32,
+}
+impl Default for EnvelopeVoice {
+    fn default() -> Self {
+        Self {
+            stage: Stage::Delay,
+            elapsed: 0.0,
+            start_level: 0.0,
+            level: 0.0,
+            attack_scale: 1.0,
+        }
+    }
+}
+impl EnvelopeVoice {
+    /// Starts the envelope again, attacking from the current level.
+    pub fn retrigger(&mut self) {
+        self.enter(Stage::Delay, 0.0);
+    }
+    /// Starts the release stage from the current level.
+    pub fn release(&mut self) {
+        if !matches!(self.stage, Stage::Release | Stage::Finished) {
+            self.enter(Stage::Release, 0.0);
+        }
+    }
+    pub fn set_attack_scale(&mut self, scale: f32) {
+        self.attack_scale = scale;
+    }
+    /// True once the release has finished, after which the envelope stays at 0.
+    pub fn is_finished(&self) -> bool {
+        self.stage == Stage::Finished
+    }
+    #[cfg(test)]
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+    fn enter(&mut self, stage: Stage, elapsed: f32) {
+        self.stage = stage;
+        self.elapsed = elapsed;
+        self.start_level = self.level;
+    }
+    /// Advances by `time_step` seconds and returns the new level (between 0.0 and 1.0).
+    pub fn next(&mut self, envelope: &DahdsrEnvelope, time_step: f32) -> f32 {
+        self.elapsed += time_step;
+        loop {
+            let (length, next) = match self.stage {
+                Stage::Delay => (envelope.delay_time, Stage::Attack),
+                Stage::Attack => (envelope.attack_time * self.attack_scale, Stage::Hold),
+                Stage::Hold => (envelope.hold_time, Stage::Decay),
+                Stage::Decay => (envelope.decay_time, Stage::Sustain),
+                Stage::Release => (envelope.release_time, Stage::Finished),
+                Stage::Sustain => {
+                    self.level = (envelope.sustain_level + envelope.sustain_slope * self.elapsed)
+                        .clamp(0.0, 1.0);
+                    return self.level;
+                }
+                Stage::Finished => {
+                    self.level = 0.0;
+                    return self.level;
+                }
+            };
+            if self.elapsed < length {
+                let progress = self.elapsed / length;
+                self.level = match self.stage {
+                    Stage::Attack => lerp(
+                        self.start_level,
+                        1.0,
+                        progress.powf(envelope.attack_exponent),
+                    ),
+                    Stage::Decay => lerp(
+                        envelope.sustain_level,
+                        self.start_level,
+                        (1.0 - progress).powf(envelope.decay_exponent),
+                    ),
+                    Stage::Release => {
+                        (1.0 - progress).powf(envelope.release_exponent) * self.start_level
+                    }
+                    // Delay and hold keep the current level
+                    _ => self.level,
+                };
+                return self.level;
+            }
+
+            // Stage finished
+            self.level = match self.stage {
+                Stage::Attack => 1.0,
+                Stage::Decay => envelope.sustain_level,
+                Stage::Release => 0.0,
+                _ => self.level,
+            };
+            self.enter(next, self.elapsed - length);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `voice` for `time` seconds in 1 ms steps, returning the final level.
+    fn run(voice: &mut EnvelopeVoice, envelope: &DahdsrEnvelope, time: f32) -> f32 {
+        let steps = (time * 1000.0).round() as usize;
+        (0..steps).fold(voice.level(), |_, _| voice.next(envelope, 0.001))
+    }
+
+    fn assert_near(level: f32, expected: f32) {
+        assert!((level - expected).abs() < 1e-3, "{level} != {expected}");
+    }
+
+    #[test]
+    fn stages_follow_each_other() {
+        let mut envelope = DahdsrEnvelope::new(1.0, 1.0, 1.0, 0.5, 0.0);
+        envelope.delay_time = 1.0;
+        envelope.hold_time = 1.0;
+        envelope.sustain_slope = -0.25;
+        let mut voice = EnvelopeVoice::default();
+
+        assert_near(run(&mut voice, &envelope, 0.5), 0.0);
+        assert_near(run(&mut voice, &envelope, 1.0), 0.5);
+        assert_near(run(&mut voice, &envelope, 1.0), 1.0);
+        assert_near(run(&mut voice, &envelope, 1.0), 0.75);
+        assert_near(run(&mut voice, &envelope, 0.5), 0.5);
+        assert_near(run(&mut voice, &envelope, 1.0), 0.25);
+        assert_near(run(&mut voice, &envelope, 3.0), 0.0);
+    }
+
+    #[test]
+    fn zero_length_stages_are_skipped() {
+        let envelope = DahdsrEnvelope::new(0.0, 0.0, 0.0, 0.5, 1.0);
+        let mut voice = EnvelopeVoice::default();
+        assert_eq!(voice.next(&envelope, 0.001), 0.5);
+        voice.release();
+        assert_eq!(voice.next(&envelope, 0.001), 0.0);
+        assert!(voice.is_finished());
+    }
+
+    #[test]
+    fn curves_are_independent() {
+        let mut envelope = DahdsrEnvelope::new(1.0, 1.0, 1.0, 0.0, 0.0);
+        envelope.set_attack_curve(2.0);
+        let mut voice = EnvelopeVoice::default();
+        assert!(run(&mut voice, &envelope, 0.5) > 0.5);
+        assert_near(run(&mut voice, &envelope, 1.0), 0.5);
+
+        envelope.set_decay_curve(2.0);
+        let mut voice = EnvelopeVoice::default();
+        assert!(run(&mut voice, &envelope, 1.5) < 0.5);
+    }
+
+    #[test]
+    fn release_starts_from_current_level() {
+        let envelope = DahdsrEnvelope::new(1.0, 1.0, 1.0, 1.0, 0.0);
+        let mut voice = EnvelopeVoice::default();
+        assert_near(run(&mut voice, &envelope, 0.5), 0.5);
+        voice.release();
+        assert_near(run(&mut voice, &envelope, 0.001), 0.4995);
+        assert_near(run(&mut voice, &envelope, 0.499), 0.25);
+        assert_near(run(&mut voice, &envelope, 0.51), 0.0);
+        assert!(voice.is_finished());
+    }
+
+    #[test]
+    fn retrigger_attacks_from_current_level() {
+        let mut envelope = DahdsrEnvelope::new(1.0, 1.0, 1.0, 1.0, 0.0);
+        envelope.delay_time = 0.5;
+        let mut voice = EnvelopeVoice::default();
+        run(&mut voice, &envelope, 2.0);
+        voice.release();
+        assert_near(run(&mut voice, &envelope, 0.5), 0.5);
+
+        // The delay holds the current level rather than dropping to 0.
+        voice.retrigger();
+        assert_near(run(&mut voice, &envelope, 0.5), 0.5);
+        assert_near(run(&mut voice, &envelope, 0.5), 0.75);
+        assert_near(run(&mut voice, &envelope, 0.5), 1.0);
+    }
+
+    #[test]
+    fn attack_scale_shortens_attack() {
+        let envelope = DahdsrEnvelope::new(1.0, 1.0, 1.0, 1.0, 0.0);
+        let mut voice = EnvelopeVoice::default();
+        voice.set_attack_scale(0.5);
+        assert_near(run(&mut voice, &envelope, 0.25), 0.5);
+        assert_near(run(&mut voice, &envelope, 0.25), 1.0);
+    }
+
+    #[test]
+    fn steps_are_small_around_every_event() {
+        let envelope = DahdsrEnvelope::new(0.01, 0.1, 0.05, 0.5, 0.0);
+        let mut voice = EnvelopeVoice::default();
+        let step = 1.0 / 48000.0;
+        let mut last = 0.0;
+        for i in 0..48000 {
+            // Release and retrigger at awkward points in every stage
+            match i {
+                200 | 3000 | 9000 | 30000 => voice.release(),
+                1000 | 4000 | 20000 => voice.retrigger(),
+                _ => {}
+            }
+            let level = voice.next(&envelope, step);
+            assert!((level - last).abs() < 0.01, "jump at sample {i}");
+            last = level;
+        }
+    }
+}
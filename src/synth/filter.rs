@@ -1,9 +1,11 @@
+use self::drive::{DcBlocker, DrivePosition, DriveShape};
 use self::ladder::{tanh_pade32_f32, LadderFilter};
 use self::svf_simper::SvfSimper;
 
 use super::lerp;
 
-use super::envelopes::AdsrEnvelope;
+use super::envelopes::DahdsrEnvelope;
+use super::smoothing::{Smoother, SmoothingBlock};
 
 use std::f32::consts::PI;
 
@@ -66,7 +68,8 @@ impl BiquadFilter {
         filter_type: &FilterType,
     ) -> (f32, f32, f32, f32, f32, f32) {
         // Biquad is less stable than other filters at low frequencies, clamp to 30 Hz minimum.
-        let cutoff = cutoff.max(30.0);
+        // Cutoffs past Nyquist fold back down, so keep it just below half the sample rate.
+        let cutoff = cutoff.clamp(30.0, sample_rate * 0.49);
 
         // Coefficients and formulas from https://www.w3.org/TR/audio-eq-cookbook/
 
@@ -176,50 +179,180 @@ pub(crate) trait Filter {
 /// Applies an envelope to something that implements the `Filter` trait.
 /// Also handles keytrack.
 pub(crate) struct FilterController {
-    pub(crate) cutoff_envelope: AdsrEnvelope,
+    pub(crate) cutoff_envelope: DahdsrEnvelope,
+    /// Envelope depth in octaves, which can be negative to sweep the cutoff downwards.
     pub(crate) envelope_amount: f32,
-    pub(crate) cutoff: f32,
+    /// How much a note's velocity scales the envelope depth, from 0 (not at all) to 1.
+    pub(crate) envelope_velocity: f32,
     pub(crate) target_cutoff: f32,
     pub(crate) resonance: f32,
     pub(crate) drive: f32,
+    pub(crate) drive_shape: DriveShape,
+    pub(crate) drive_position: DrivePosition,
     pub(crate) keytrack: f32,
+    pub(crate) keytrack_mode: KeytrackMode,
     pub(crate) filter_type: FilterType,
     pub(crate) filter_model: FilterModel,
+    smoothers: FilterSmoothers,
+}
+
+#[derive(Debug, Default)]
+struct FilterSmoothers {
+    envelope_amount: Smoother,
+    envelope_velocity: Smoother,
+    cutoff: Smoother,
+    resonance: Smoother,
+    drive: Smoother,
+    keytrack: Smoother,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Snapshot of the filter's smoothed controls at a single sample.
+pub(crate) struct SmoothedFilter {
+    pub(crate) envelope_amount: f32,
+    pub(crate) envelope_velocity: f32,
+    pub(crate) cutoff: f32,
+    pub(crate) resonance: f32,
+    pub(crate) drive: f32,
+    pub(crate) keytrack: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// What a voice feeds into its cutoff at a single sample.
+pub(crate) struct CutoffInputs {
+    /// MIDI note number including any glide, which Relative keytrack follows.
+    pub(crate) note: f32,
+    /// Frequency actually played in Hz, which Calibrated keytrack follows so it stays in tune.
+    pub(crate) frequency: f32,
+    /// From 0 to 1.
+    pub(crate) velocity: f32,
+    /// Multiplies the cutoff, e.g. from the LFO and MSEG.
+    pub(crate) mod_mult: f32,
+    /// Level of the cutoff envelope.
+    pub(crate) envelope: f32,
 }
 
 impl FilterController {
     pub(crate) fn new() -> Self {
         Self {
-            cutoff_envelope: AdsrEnvelope::new(0.0, 0.0, 0.0, 1.0, 1.0),
+            cutoff_envelope: DahdsrEnvelope::new(0.0, 0.0, 0.0, 1.0, 1.0),
             envelope_amount: 0.0,
-            cutoff: 100.0,
+            envelope_velocity: 0.0,
             target_cutoff: 100.0,
             resonance: 0.1,
             drive: 1.0,
+            drive_shape: DriveShape::Clean,
+            drive_position: DrivePosition::PreFilter,
             keytrack: 0.0,
+            keytrack_mode: KeytrackMode::Relative,
             filter_type: FilterType::Lowpass,
             filter_model: FilterModel::RcFilter,
+            smoothers: Default::default(),
         }
     }
-    pub fn get_cutoff(
+    /// Runs `input` through the drive stage and `filter`.
+    pub fn process(
         &self,
-        cutoff_mult: f32,
-        envelope_index: f32,
-        release_index: Option<u32>,
+        filter: &mut FilterContainer,
+        dc_blocker: &mut DcBlocker,
+        input: f32,
+        drive: f32,
         sample_rate: f32,
     ) -> f32 {
-        let envelope = if let Some(release_index) = release_index {
-            let release_time = release_index as f32 / sample_rate as f32;
-            self.cutoff_envelope
-                .sample_released(release_time, envelope_index)
-        } else {
-            self.cutoff_envelope.sample_held(envelope_index)
+        // The ladder is much louder than the other models at the same input level
+        let model_gain = match filter {
+            FilterContainer::LadderFilter(_) => 0.5,
+            _ => 1.0,
         };
-        let envelope = envelope * self.envelope_amount * (440.0 + self.cutoff * cutoff_mult) * 50.0;
-        (self.cutoff * cutoff_mult + envelope).clamp(10.0, 22000.0)
+
+        if self.drive_shape == DriveShape::Clean {
+            let drive = match filter {
+                // Biquad/SVF/none filters are unaffected by drive, so we clamp it between 0 and 1 to
+                // keep the levels the same when switching filter.
+                FilterContainer::BiquadFilter(_)
+                | FilterContainer::SvfSimperFilter(_)
+                | FilterContainer::None => drive.min(1.0),
+                _ => drive,
+            };
+            return filter.process(input * drive * model_gain);
+        }
+
+        let output = match self.drive_position {
+            DrivePosition::PreFilter => {
+                filter.process(self.drive_shape.shape(input * drive) * model_gain)
+            }
+            DrivePosition::PostFilter => {
+                self.drive_shape.shape(filter.process(input * model_gain) * drive)
+            }
+        };
+        dc_blocker.process(output, DcBlocker::coefficient(sample_rate))
+    }
+    /// Moves the smoothed copies of each continuous control towards their current values.
+    pub fn update_smoothing(&mut self, block: &SmoothingBlock) {
+        self.smoothers
+            .envelope_amount
+            .next_block(self.envelope_amount, block);
+        self.smoothers
+            .envelope_velocity
+            .next_block(self.envelope_velocity, block);
+        self.smoothers.cutoff.next_block(self.target_cutoff, block);
+        self.smoothers.resonance.next_block(self.resonance, block);
+        self.smoothers.drive.next_block(self.drive, block);
+        self.smoothers.keytrack.next_block(self.keytrack, block);
+    }
+    /// Returns the smoothed controls `index` samples into the current buffer.
+    #[inline]
+    pub fn smoothed(&self, index: usize) -> SmoothedFilter {
+        SmoothedFilter {
+            envelope_amount: self.smoothers.envelope_amount.at(index),
+            envelope_velocity: self.smoothers.envelope_velocity.at(index),
+            cutoff: self.smoothers.cutoff.at(index),
+            resonance: self.smoothers.resonance.at(index),
+            drive: self.smoothers.drive.at(index),
+            keytrack: self.smoothers.keytrack.at(index),
+        }
+    }
+    /// Returns the cutoff for a voice with `inputs`.
+    pub fn get_cutoff(
+        &self,
+        controls: &SmoothedFilter,
+        inputs: &CutoffInputs,
+        sample_rate: f32,
+    ) -> f32 {
+        let depth =
+            controls.envelope_amount * lerp(1.0, inputs.velocity, controls.envelope_velocity);
+        let cutoff = match self.keytrack_mode {
+            KeytrackMode::Relative => {
+                controls.cutoff * ((inputs.note - 69.0) / 12.0 * controls.keytrack).exp2()
+            }
+            KeytrackMode::Calibrated => {
+                lerp(controls.cutoff.ln(), inputs.frequency.ln(), controls.keytrack).exp()
+            }
+        } * inputs.mod_mult
+            * (inputs.envelope * depth).exp2();
+        let cutoff = cutoff.clamp(10.0, 22000.0);
+        match self.keytrack_mode {
+            KeytrackMode::Relative => cutoff,
+            KeytrackMode::Calibrated => tuning::corrected_cutoff(
+                self.filter_model,
+                cutoff,
+                controls.resonance,
+                sample_rate,
+            )
+            .clamp(10.0, 22000.0),
+        }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeytrackMode {
+    /// Keytrack scales the cutoff relative to A4 (440 Hz).
+    Relative,
+    /// Keytrack moves the cutoff towards the note's frequency, corrected for each model so
+    /// that the self-oscillation is in tune.
+    Calibrated,
+}
+
 #[derive(Debug, Clone)]
 /// A filter that can be switched between multiple filter modes.
 // TODO: There must be a better way to do this.
@@ -240,18 +373,29 @@ impl FilterContainer {
         filter_type: FilterType,
     ) {
         match (self, filter_model) {
-            (FilterContainer::RcFilter(_), FilterModel::RcFilter) => {}
+            (FilterContainer::RcFilter(x), FilterModel::RcFilter) => {
+                x.saturation = RcSaturation::HardClip
+            }
+            (FilterContainer::RcFilter(x), FilterModel::RcFilterSoft) => {
+                x.saturation = RcSaturation::Tanh
+            }
             (FilterContainer::LadderFilter(_), FilterModel::LadderFilter) => {}
             (FilterContainer::BiquadFilter(_), FilterModel::BiquadFilter) => {}
             (FilterContainer::SvfSimperFilter(_), FilterModel::SvfSimperFilter) => {}
-            (x, FilterModel::RcFilter) => {
+            (x, FilterModel::RcFilter | FilterModel::RcFilterSoft) => {
                 let mut filter = RcFilter::new(sample_rate, cutoff, resonance);
                 filter.set_filter_type(filter_type);
                 filter.order = FilterOrder::_24dB;
+                if let FilterModel::RcFilterSoft = filter_model {
+                    filter.saturation = RcSaturation::Tanh;
+                }
                 *x = FilterContainer::RcFilter(filter)
             },
             (x, FilterModel::LadderFilter) => {
-                *x = FilterContainer::LadderFilter(LadderFilter::default())
+                let mut filter = LadderFilter::new(sample_rate as f64);
+                filter.set_params(sample_rate, cutoff, resonance);
+                filter.set_filter_type(filter_type);
+                *x = FilterContainer::LadderFilter(filter)
             }
             (x, FilterModel::BiquadFilter) => {
                 *x = FilterContainer::BiquadFilter(BiquadFilter::with_params(
@@ -310,6 +454,8 @@ impl Filter for FilterContainer {
 pub enum FilterModel {
     None,
     RcFilter,
+    /// RC filter which saturates with tanh instead of hard clipping.
+    RcFilterSoft,
     LadderFilter,
     BiquadFilter,
     SvfSimperFilter,
@@ -334,6 +480,19 @@ pub enum FilterOrder {
     _24dB,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// How the RC filter keeps each stage from blowing up at high resonance.
+pub enum RcSaturation {
+    /// Clamps every stage to [-1, 1], as in the original LMMS filter.
+    HardClip,
+    /// Saturates every stage with tanh, giving a rounder self-oscillation.
+    Tanh,
+}
+
+/// Output gain of the tanh RC filter. Hard clipping squares off loud signals, making them
+/// louder, so this was measured to match the two modes on average over typical settings.
+const RC_TANH_COMPENSATION: f32 = 1.2;
+
 /// Port of the LMMS RC filters (originally from https://github.com/LMMS/lmms/blob/master/include/BasicFilters.h)
 #[derive(Debug, Clone)]
 pub struct RcFilter {
@@ -356,6 +515,7 @@ pub struct RcFilter {
 
     order: FilterOrder,
     filter_type: FilterType,
+    saturation: RcSaturation,
 }
 #[allow(dead_code)]
 impl RcFilter {
@@ -486,6 +646,7 @@ impl RcFilter {
             hp1: 0.0,
             order: FilterOrder::_12dB,
             filter_type: FilterType::Lowpass,
+            saturation: RcSaturation::HardClip,
         }
     }
 }
@@ -496,13 +657,24 @@ impl Filter for RcFilter {
     // can be driven up to self-oscillation (BTW: do not remove the limits!!!).
     // (C) 1998 ... 2009 S.Fendt. Released under the GPL v2.0  or any later version.
     fn process(&mut self, input: f32) -> f32 {
-        match &self.order {
-            FilterOrder::_12dB => match &self.filter_type {
+        match (&self.saturation, &self.order) {
+            (RcSaturation::HardClip, FilterOrder::_12dB) => match &self.filter_type {
                 FilterType::Lowpass => self.filter_all(input).0,
                 FilterType::Bandpass => self.filter_all(input).1,
                 FilterType::Highpass => self.filter_all(input).2,
             },
-            FilterOrder::_24dB => self.filter_2nd_order(input),
+            (RcSaturation::HardClip, FilterOrder::_24dB) => self.filter_2nd_order(input),
+            (RcSaturation::Tanh, FilterOrder::_12dB) => {
+                let output = match &self.filter_type {
+                    FilterType::Lowpass => self.filter_all_tanh(input).0,
+                    FilterType::Bandpass => self.filter_all_tanh(input).1,
+                    FilterType::Highpass => self.filter_all_tanh(input).2,
+                };
+                output * RC_TANH_COMPENSATION
+            }
+            (RcSaturation::Tanh, FilterOrder::_24dB) => {
+                self.filter_2nd_order_tanh(input) * RC_TANH_COMPENSATION
+            }
         }
     }
 
@@ -536,9 +708,263 @@ impl Default for RcFilter {
             hp1: Default::default(),
             order: FilterOrder::_24dB,
             filter_type: FilterType::Lowpass,
+            saturation: RcSaturation::HardClip,
         }
     }
 }
 
+pub(crate) mod drive;
 mod ladder;
-mod svf_simper;
\ No newline at end of file
+mod svf_simper;
+mod tuning;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATES: [f32; 4] = [44100.0, 48000.0, 96000.0, 192000.0];
+
+    /// Gain in dB of a sine at `freq` through `model` after it has settled.
+    fn gain_db(model: FilterModel, sample_rate: f32, cutoff: f32, freq: f32) -> f32 {
+        let resonance = 0.7;
+        let mut filter = FilterContainer::None;
+        filter.set(model, cutoff, resonance, sample_rate, FilterType::Lowpass);
+
+        let len = (sample_rate * 0.25) as usize;
+        let (mut power_in, mut power_out) = (0.0, 0.0);
+        for i in 0..len {
+            filter.set_params(sample_rate, cutoff, resonance);
+            let input = 0.1 * (2.0 * PI * freq * i as f32 / sample_rate).sin();
+            let output = filter.process(input);
+            if i > len / 2 {
+                power_in += input * input;
+                power_out += output * output;
+            }
+        }
+        10.0 * (power_out / power_in).log10()
+    }
+
+    /// Asserts the gain at the cutoff frequency stays within `tolerance` dB of its value at
+    /// 192 kHz, where the filters are closest to their analog prototypes.
+    fn assert_cutoff_consistent(model: FilterModel, cutoffs: &[f32], tolerance: f32) {
+        for &cutoff in cutoffs {
+            let reference = gain_db(model, 192000.0, cutoff, cutoff);
+            for sample_rate in SAMPLE_RATES {
+                let gain = gain_db(model, sample_rate, cutoff, cutoff);
+                assert!(
+                    (gain - reference).abs() < tolerance,
+                    "{model:?} at {sample_rate} Hz: {gain} dB at {cutoff} Hz cutoff, expected {reference} dB"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ladder_cutoff_accuracy() {
+        assert_cutoff_consistent(
+            FilterModel::LadderFilter,
+            &[100.0, 1000.0, 5000.0, 10000.0],
+            0.5,
+        );
+    }
+
+    #[test]
+    fn rc_cutoff_accuracy() {
+        assert_cutoff_consistent(FilterModel::RcFilter, &[100.0, 1000.0, 5000.0], 1.0);
+    }
+
+    #[test]
+    fn rc_soft_level_matches_hard() {
+        let sample_rate = 48000.0;
+        let rms = |model, cutoff, resonance| {
+            let mut filter = FilterContainer::None;
+            filter.set(model, cutoff, resonance, sample_rate, FilterType::Lowpass);
+            let len = sample_rate as usize / 2;
+            let mut power = 0.0;
+            for i in 0..len {
+                let input = 0.5 * (((i as f32 * 110.0 / sample_rate) % 1.0) * 2.0 - 1.0);
+                let output = filter.process(input);
+                if i >= len / 2 {
+                    power += output * output;
+                }
+            }
+            power.sqrt()
+        };
+        for resonance in [0.7, 2.0] {
+            for cutoff in [500.0, 1000.0, 2000.0, 5000.0] {
+                let hard = rms(FilterModel::RcFilter, cutoff, resonance);
+                let soft = rms(FilterModel::RcFilterSoft, cutoff, resonance);
+                let difference = 20.0 * (soft / hard).log10();
+                assert!(
+                    difference.abs() < 2.0,
+                    "RC (Soft) is {difference} dB off at {cutoff} Hz, resonance {resonance}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn envelope_sweeps_in_octaves() {
+        let controller = FilterController::new();
+        let controls = |envelope_amount, envelope_velocity| SmoothedFilter {
+            envelope_amount,
+            envelope_velocity,
+            cutoff: 1000.0,
+            resonance: 0.7,
+            drive: 1.0,
+            keytrack: 0.0,
+        };
+        let cutoff = |controls: &SmoothedFilter, velocity, envelope| {
+            let inputs = CutoffInputs {
+                note: 69.0,
+                frequency: 440.0,
+                velocity,
+                mod_mult: 1.0,
+                envelope,
+            };
+            controller.get_cutoff(controls, &inputs, 48000.0)
+        };
+
+        assert_eq!(cutoff(&controls(2.0, 0.0), 1.0, 0.0), 1000.0);
+        assert_eq!(cutoff(&controls(2.0, 0.0), 1.0, 1.0), 4000.0);
+        assert_eq!(cutoff(&controls(2.0, 0.0), 1.0, 0.5), 2000.0);
+        assert_eq!(cutoff(&controls(-1.0, 0.0), 1.0, 1.0), 500.0);
+        // Velocity only scales the depth as much as it's told to.
+        assert_eq!(cutoff(&controls(2.0, 0.0), 0.5, 1.0), 4000.0);
+        assert_eq!(cutoff(&controls(2.0, 1.0), 0.5, 1.0), 2000.0);
+    }
+
+    #[test]
+    fn relative_keytrack_follows_the_note() {
+        let controller = FilterController::new();
+        let controls = SmoothedFilter {
+            envelope_amount: 0.0,
+            envelope_velocity: 0.0,
+            cutoff: 1000.0,
+            resonance: 0.7,
+            drive: 1.0,
+            keytrack: 1.0,
+        };
+        // An octave up doubles the cutoff, whatever pitch the oscillators are detuned to
+        for frequency in [880.0, 900.0] {
+            let inputs = CutoffInputs {
+                note: 81.0,
+                frequency,
+                velocity: 1.0,
+                mod_mult: 1.0,
+                envelope: 0.0,
+            };
+            let cutoff = controller.get_cutoff(&controls, &inputs, 48000.0);
+            assert!((cutoff - 2000.0).abs() < 0.01);
+        }
+    }
+
+    /// Measures the pitch a filter self-oscillates at after a short impulse.
+    fn self_oscillation_pitch(filter: &mut FilterContainer, sample_rate: f32) -> f32 {
+        let len = sample_rate as usize;
+        let (mut last, mut first, mut latest, mut cycles) = (0.0, None, 0.0, 0);
+        for i in 0..len {
+            let output = filter.process(if i < 4 { 0.5 } else { 0.0 });
+            if i > len / 2 && last < 0.0 && output >= 0.0 {
+                let crossing = i as f32 - 1.0 + last / (last - output);
+                match first {
+                    None => first = Some(crossing),
+                    Some(_) => {
+                        latest = crossing;
+                        cycles += 1;
+                    }
+                }
+            }
+            last = output;
+        }
+        cycles as f32 * sample_rate / (latest - first.unwrap())
+    }
+
+    #[test]
+    fn calibrated_keytrack_is_in_tune() {
+        let mut controller = FilterController::new();
+        controller.keytrack_mode = KeytrackMode::Calibrated;
+        for model in [
+            FilterModel::RcFilter,
+            FilterModel::RcFilterSoft,
+            FilterModel::LadderFilter,
+        ] {
+            controller.filter_model = model;
+            for sample_rate in [44100.0, 96000.0] {
+                for resonance in [9.0, 10.0] {
+                    let controls = SmoothedFilter {
+                        envelope_amount: 0.0,
+                        envelope_velocity: 0.0,
+                        cutoff: 22000.0,
+                        resonance,
+                        drive: 1.0,
+                        keytrack: 1.0,
+                    };
+                    for note in [36, 60, 84] {
+                        let frequency = 440.0 * 2.0_f32.powf((note - 69) as f32 / 12.0);
+                        let inputs = CutoffInputs {
+                            note: note as f32,
+                            frequency,
+                            velocity: 1.0,
+                            mod_mult: 1.0,
+                            envelope: 0.0,
+                        };
+                        let cutoff = controller.get_cutoff(&controls, &inputs, sample_rate);
+                        let mut filter = FilterContainer::None;
+                        filter.set(model, cutoff, resonance, sample_rate, FilterType::Lowpass);
+                        let pitch = self_oscillation_pitch(&mut filter, sample_rate);
+                        let cents = 1200.0 * (pitch / frequency).log2();
+                        assert!(
+                            cents.abs() < 10.0,
+                            "{model:?} is {cents} cents off at note {note}, \
+                            resonance {resonance}, {sample_rate} Hz"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn digital_cutoff_accuracy() {
+        // Both digital filters are prewarped, so the gain at cutoff should be exact everywhere
+        for sample_rate in SAMPLE_RATES {
+            for cutoff in [100.0, 1000.0, 5000.0, 10000.0] {
+                let biquad = gain_db(FilterModel::BiquadFilter, sample_rate, cutoff, cutoff);
+                assert!((biquad - 20.0 * 0.7_f32.log10()).abs() < 0.1, "{biquad} dB");
+                let svf = gain_db(FilterModel::SvfSimperFilter, sample_rate, cutoff, cutoff);
+                let k: f32 = 2.0 - 1.9 * 0.07;
+                assert!((svf + 20.0 * k.log10()).abs() < 0.1, "{svf} dB");
+            }
+        }
+    }
+    #[test]
+    fn drive_shapes_are_bounded_and_dc_free() {
+        let sample_rate = 48000.0;
+        for shape in [
+            DriveShape::Tanh,
+            DriveShape::SoftClip,
+            DriveShape::Tube,
+            DriveShape::Diode,
+            DriveShape::Foldback,
+        ] {
+            let mut controller = FilterController::new();
+            controller.drive_shape = shape;
+            let mut filter = FilterContainer::None;
+            let mut dc_blocker = DcBlocker::default();
+
+            let len = sample_rate as usize;
+            let mut sum = 0.0;
+            for i in 0..len {
+                let input = (2.0 * PI * 100.0 * i as f32 / sample_rate).sin();
+                let output =
+                    controller.process(&mut filter, &mut dc_blocker, input, 8.0, sample_rate);
+                assert!(output.abs() < 2.5, "{shape:?} output {output}");
+                if i >= len / 2 {
+                    sum += output;
+                }
+            }
+            let dc = sum / (len / 2) as f32;
+            assert!(dc.abs() < 0.01, "{shape:?} has DC offset {dc}");
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::f32::consts::PI;
+
+use super::ladder::tanh_pade32_f32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Waveshaper applied by the filter's drive stage.
+pub enum DriveShape {
+    /// Plain gain, letting the filter model provide any distortion.
+    Clean,
+    Tanh,
+    /// Cubic soft clipper, reaching full level at an input of 1.
+    SoftClip,
+    /// Biased tanh, which clips the top of the wave before the bottom (even harmonics).
+    Tube,
+    /// Exponential diode pair, with a softer knee than tanh.
+    Diode,
+    /// Reflects the wave back down whenever it goes past full level.
+    Foldback,
+}
+impl DriveShape {
+    #[inline]
+    pub fn shape(&self, input: f32) -> f32 {
+        match self {
+            DriveShape::Clean => input,
+            DriveShape::Tanh => tanh_pade32_f32(input),
+            DriveShape::SoftClip => {
+                let x = input.clamp(-1.0, 1.0);
+                1.5 * x - 0.5 * x * x * x
+            }
+            DriveShape::Tube => {
+                let bias = 0.3;
+                tanh_pade32_f32(input + bias) - tanh_pade32_f32(bias)
+            }
+            DriveShape::Diode => (1.0 - (-input.abs()).exp()).copysign(input),
+            DriveShape::Foldback => 1.0 - ((input + 1.0).rem_euclid(4.0) - 2.0).abs(),
+        }
+    }
+    pub fn from_index(index: f32) -> Self {
+        match index {
+            x if x < 1.0 => DriveShape::Clean,
+            x if x < 2.0 => DriveShape::Tanh,
+            x if x < 3.0 => DriveShape::SoftClip,
+            x if x < 4.0 => DriveShape::Tube,
+            x if x < 5.0 => DriveShape::Diode,
+            _ => DriveShape::Foldback,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DrivePosition {
+    PreFilter,
+    PostFilter,
+}
+
+#[derive(Debug, Clone, Default)]
+/// One-pole highpass which removes the DC offset left by asymmetric waveshapers.
+pub struct DcBlocker {
+    last_input: f32,
+    last_output: f32,
+}
+impl DcBlocker {
+    /// Pole position for a cutoff of roughly 10 Hz.
+    pub fn coefficient(sample_rate: f32) -> f32 {
+        1.0 - 2.0 * PI * 10.0 / sample_rate
+    }
+    #[inline]
+    pub fn process(&mut self, input: f32, coefficient: f32) -> f32 {
+        self.last_output = input - self.last_input + coefficient * self.last_output;
+        self.last_input = input;
+        self.last_output
+    }
+}
@@ -92,16 +92,19 @@ pub struct LadderFilter {
     iir_lowpass: IirFilter,
 }
 
-impl Default for LadderFilter {
-    fn default() -> Self {
+impl LadderFilter {
+    /// Creates a filter whose integration rate and downsampling filter are designed for
+    /// `sample_rate`.
+    pub fn new(sample_rate: f64) -> Self {
+        let oversampling_factor = 3;
         Self {
             cutoff_frequency: 0.25,
             resonance: 0.5,
             ladder_filter_mode: FilterType::Lowpass,
-            sample_rate: 44100.0,
-            dt: integration_rate(44100.0, 3, 0.25),
+            sample_rate,
+            dt: integration_rate(sample_rate, oversampling_factor, 0.25),
             ladder_integration_method: IntegrationMethod::PredictorCorrectorFullTanh,
-            oversampling_factor: 3,
+            oversampling_factor,
             decimator_order: IIR_DOWNSAMPLE_ORDER,
             p0: 0.0,
             p1: 0.0,
@@ -110,8 +113,8 @@ impl Default for LadderFilter {
             ut_1: 0.0,
             out: 0.0,
             iir_lowpass: IirFilter::new_lowpass(
-                44100.0 * 2.0,
-                IIR_DOWNSAMPLING_BANDWIDTH * 44100.0 / 2.0,
+                sample_rate * oversampling_factor as f64,
+                IIR_DOWNSAMPLING_BANDWIDTH * sample_rate / 2.0,
                 IIR_DOWNSAMPLE_ORDER,
             ),
         }
@@ -119,9 +122,6 @@ impl Default for LadderFilter {
 }
 #[allow(dead_code)]
 impl LadderFilter {
-    pub fn new() -> Self {
-        Self::default()
-    }
     pub fn process_sample(&mut self, mut input: f64) {
         let feedback = 8.0 * self.resonance;
         let noise = 1.0e-6 * 2.0 * (Rng::new().f64() - 0.5);
@@ -247,26 +247,28 @@ impl LadderFilter {
                     self.p3 = c * p3_prime + b * (p2_prime + self.p2);
                 }
             }
-        }
 
-        self.ut_1 = input;
+            self.ut_1 = input;
 
-        match self.ladder_filter_mode {
-            FilterType::Lowpass => {
-                self.out = self.p3;
-            }
-            FilterType::Bandpass => {
-                self.out = self.p1 - self.p3;
-            }
-            FilterType::Highpass => {
-                self.out = tanh_pade32(input - self.p0 - feedback * self.p3);
+            match self.ladder_filter_mode {
+                FilterType::Lowpass => {
+                    self.out = self.p3;
+                }
+                FilterType::Bandpass => {
+                    self.out = self.p1 - self.p3;
+                }
+                FilterType::Highpass => {
+                    self.out = tanh_pade32(input - self.p0 - feedback * self.p3);
+                }
+                #[allow(unreachable_patterns)]
+                _ => self.out = 0.0,
             }
-            #[allow(unreachable_patterns)]
-            _ => self.out = 0.0,
-        }
 
-        if self.oversampling_factor > 1 {
-            self.out = self.iir_lowpass.filter(self.out);
+            // downsampling filter runs at the oversampled rate, so it must see every
+            // intermediate step
+            if self.oversampling_factor > 1 {
+                self.out = self.iir_lowpass.filter(self.out);
+            }
         }
     }
     pub fn output(&self) -> f64 {
@@ -290,10 +292,15 @@ impl LadderFilter {
         self.ladder_filter_mode = mode;
     }
     pub fn set_sample_rate(&mut self, sample_rate: f64) {
-        self.sample_rate = sample_rate;
-        self.iir_lowpass.sample_rate = sample_rate * self.oversampling_factor as f64;
-        self.iir_lowpass.cutoff_frequency = IIR_DOWNSAMPLING_BANDWIDTH * sample_rate / 2.0;
-        self.iir_lowpass.decimator_order = self.decimator_order;
+        // Redesigning the decimator clears its state, so only do it when the rate changes
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.iir_lowpass.decimator_order = self.decimator_order;
+            self.iir_lowpass
+                .set_sample_rate(sample_rate * self.oversampling_factor as f64);
+            self.iir_lowpass
+                .set_cutoff(IIR_DOWNSAMPLING_BANDWIDTH * sample_rate / 2.0);
+        }
 
         self.set_integration_rate();
     }
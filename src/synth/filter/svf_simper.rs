@@ -52,6 +52,8 @@ pub struct SvfSimper {
 
 impl SvfSimper {
     pub fn new(cutoff: f32, resonance: f32, sample_rate: f32) -> Self {
+        // tan() blows up at Nyquist, so keep the cutoff just below it
+        let cutoff = cutoff.min(sample_rate * 0.49);
         let g = (consts::PI * (cutoff / sample_rate)).tan();
         let k = 2f32 - (1.9f32 * resonance.clamp(0.0, 1.0));
 
@@ -79,6 +81,7 @@ impl SvfSimper {
         self.a1 = new.a1;
         self.a2 = new.a2;
         self.a3 = new.a3;
+        self.k = new.k;
     }
 }
 impl Filter for SvfSimper {
@@ -0,0 +1,67 @@
+use super::{lerp, FilterModel};
+
+/// Ratio between a filter's self-oscillation frequency and its cutoff.
+///
+/// Rows are resonance 9, 9.5 and 10, columns are cutoffs of `sample_rate * 2^-11` up to
+/// `sample_rate * 2^-3` in octave steps. Measured at 48 kHz by counting zero crossings
+/// after a short impulse.
+type CorrectionTable = [[f32; 9]; 3];
+
+#[rustfmt::skip]
+const RC_CORRECTION: CorrectionTable = [
+    [0.9845, 0.9838, 0.9825, 0.9798, 0.9746, 0.9642, 0.9441, 0.9061, 0.8367],
+    [0.9793, 0.9781, 0.9758, 0.9712, 0.9627, 0.9527, 0.9334, 0.8970, 0.8316],
+    [0.9909, 0.9899, 0.9875, 0.9832, 0.9746, 0.9576, 0.9242, 0.8868, 0.8239],
+];
+#[rustfmt::skip]
+const RC_SOFT_CORRECTION: CorrectionTable = [
+    [1.0980, 1.0965, 1.0933, 1.0870, 1.0748, 1.0519, 1.0106, 0.9418, 0.8385],
+    [1.1545, 1.1526, 1.1488, 1.1413, 1.1269, 1.1000, 1.0522, 0.9743, 0.8607],
+    [1.2089, 1.2066, 1.2021, 1.1934, 1.1765, 1.1453, 1.0909, 1.0040, 0.8805],
+];
+#[rustfmt::skip]
+const LADDER_CORRECTION: CorrectionTable = [
+    [1.0145, 0.9996, 0.9922, 0.9885, 0.9868, 0.9860, 0.9862, 0.9885, 0.9979],
+    [1.0044, 0.9899, 0.9826, 0.9790, 0.9772, 0.9764, 0.9766, 0.9787, 0.9876],
+    [0.9965, 0.9821, 0.9748, 0.9712, 0.9695, 0.9687, 0.9688, 0.9709, 0.9793],
+];
+
+/// Returns the cutoff `model` needs to self-oscillate at `frequency`.
+///
+/// Models which can't self-oscillate are returned unchanged. Below a resonance of 9 the
+/// filters don't self-oscillate either, but the resonant peak sits close enough to the
+/// resonance 9 pitch that the same correction still keeps it in tune.
+pub(super) fn corrected_cutoff(
+    model: FilterModel,
+    frequency: f32,
+    resonance: f32,
+    sample_rate: f32,
+) -> f32 {
+    // The RC filter's error grows as the cutoff gets closer to Nyquist, whereas the
+    // oversampled ladder sounds the same at every sample rate, so it's looked up at the
+    // rate it was measured at.
+    let (table, table_rate) = match model {
+        FilterModel::RcFilter => (&RC_CORRECTION, sample_rate),
+        FilterModel::RcFilterSoft => (&RC_SOFT_CORRECTION, sample_rate),
+        FilterModel::LadderFilter => (&LADDER_CORRECTION, 48000.0),
+        _ => return frequency,
+    };
+    // The ratio depends on the cutoff we're solving for, so refine the guess once.
+    let cutoff = frequency / ratio(table, frequency, resonance, table_rate);
+    frequency / ratio(table, cutoff, resonance, table_rate)
+}
+
+fn ratio(table: &CorrectionTable, cutoff: f32, resonance: f32, sample_rate: f32) -> f32 {
+    let column = ((cutoff / sample_rate).log2() + 11.0).clamp(0.0, 8.0);
+    let row = ((resonance - 9.0) * 2.0).clamp(0.0, 2.0);
+    let sample_row = |row: &[f32; 9]| {
+        let index = (column as usize).min(7);
+        lerp(row[index], row[index + 1], column - index as f32)
+    };
+    let index = (row as usize).min(1);
+    lerp(
+        sample_row(&table[index]),
+        sample_row(&table[index + 1]),
+        row - index as f32,
+    )
+}
@@ -0,0 +1,278 @@
+use std::{
+    f32::consts::PI,
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Mutex,
+    },
+    thread,
+};
+
+use super::oscillator::{AdditiveOsc, WavetableNotes};
+
+/// Number of partials with their own gain and phase controls.
+pub const EDITABLE_PARTIALS: usize = 16;
+/// Number of partials in a harmonic spectrum.
+const PARTIALS: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PartialParity {
+    All,
+    Odd,
+    /// Even partials, plus the fundamental so the pitch doesn't jump up an octave.
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// User-editable harmonic spectrum.
+///
+/// Generator settings shape every partial, then the first `EDITABLE_PARTIALS` partials can
+/// be adjusted individually.
+pub struct HarmonicParams {
+    /// Partial `n` has an amplitude of `1 / n^rolloff`, so 1 is a saw and 2 is much softer.
+    pub rolloff: f32,
+    pub parity: PartialParity,
+    /// Only keeps partials 1, 1 + n, 1 + 2n, ...
+    pub every: usize,
+    /// Partials above this are removed.
+    pub count: usize,
+    /// Gain of each editable partial relative to the generator, in dB.
+    pub gains: [f32; EDITABLE_PARTIALS],
+    /// Phase of each editable partial, in cycles.
+    pub phases: [f32; EDITABLE_PARTIALS],
+}
+impl HarmonicParams {
+    fn keeps(&self, partial: usize) -> bool {
+        let parity = match self.parity {
+            PartialParity::All => true,
+            PartialParity::Odd => !partial.is_multiple_of(2),
+            PartialParity::Even => partial.is_multiple_of(2) || partial == 1,
+        };
+        parity && (partial - 1).is_multiple_of(self.every.max(1)) && partial <= self.count
+    }
+    /// Builds the spectrum, normalised to the same RMS level as a saw.
+    pub fn spectrum(&self) -> AdditiveOsc<PARTIALS> {
+        let mut amplitudes = [0.0; PARTIALS];
+        let mut phases = [0.0; PARTIALS];
+        for (i, amplitude) in amplitudes.iter_mut().enumerate() {
+            let partial = i + 1;
+            if self.keeps(partial) {
+                *amplitude = (partial as f32).powf(-self.rolloff);
+            }
+        }
+        for (amplitude, phase, gain, cycles) in itertools::izip!(
+            amplitudes.iter_mut(),
+            phases.iter_mut(),
+            self.gains.iter(),
+            self.phases.iter(),
+        ) {
+            *amplitude *= 10.0_f32.powf(gain / 20.0);
+            *phase = cycles * 2.0 * PI;
+        }
+
+        let saw_power: f32 = (1..=PARTIALS).map(|n| (n as f32).powi(-2)).sum();
+        let power: f32 = amplitudes.iter().map(|x| x * x).sum();
+        let scale = (saw_power / power).sqrt();
+        if scale.is_finite() {
+            amplitudes.iter_mut().for_each(|x| *x *= scale);
+        }
+        AdditiveOsc::new(amplitudes, phases)
+    }
+}
+impl Default for HarmonicParams {
+    fn default() -> Self {
+        Self {
+            rolloff: 1.0,
+            parity: PartialParity::All,
+            every: 1,
+            count: PARTIALS,
+            gains: [0.0; EDITABLE_PARTIALS],
+            phases: [0.0; EDITABLE_PARTIALS],
+        }
+    }
+}
+
+/// Requests which can wait for the background thread. Sending on a bounded channel never
+/// allocates, so the audio thread can do it.
+const QUEUED_REQUESTS: usize = 8;
+
+enum Request {
+    Build(HarmonicParams),
+    /// Tables are sent back to be freed, because deallocating on the audio thread isn't
+    /// realtime safe.
+    Free(Box<WavetableNotes>),
+}
+
+/// Rebuilds the harmonic editor's bandlimited tables on a background thread, since building
+/// them takes far longer than an audio buffer.
+pub struct HarmonicBuilder {
+    requests: SyncSender<Request>,
+    /// Only accessed through `get_mut`, so this never locks. The mutex just makes the
+    /// receiver `Sync`.
+    tables: Mutex<Receiver<Box<WavetableNotes>>>,
+    /// Parameters of the last requested build.
+    requested: Option<HarmonicParams>,
+    /// Table waiting for room in the queue to be sent back and freed.
+    retired: Option<Box<WavetableNotes>>,
+    pub table: Option<Box<WavetableNotes>>,
+}
+impl HarmonicBuilder {
+    pub fn new(sample_rate: f32, table_len: usize) -> Self {
+        let (requests, request_receiver) = sync_channel(QUEUED_REQUESTS);
+        let (table_sender, tables) = sync_channel(1);
+        thread::spawn(move || {
+            // Stops once the builder is dropped
+            while let Ok(request) = request_receiver.recv() {
+                let mut params = match request {
+                    Request::Build(params) => params,
+                    Request::Free(table) => {
+                        drop(table);
+                        continue;
+                    }
+                };
+                // Only the newest parameters matter if several builds are queued
+                for request in request_receiver.try_iter() {
+                    if let Request::Build(newer) = request {
+                        params = newer;
+                    }
+                }
+                let table = WavetableNotes::from_additive_osc_ifft(
+                    &params.spectrum(),
+                    sample_rate,
+                    table_len,
+                );
+                if table_sender.send(Box::new(table)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            requests,
+            tables: Mutex::new(tables),
+            requested: None,
+            retired: None,
+            table: None,
+        }
+    }
+    /// Requests new tables if `params` have changed, and swaps in any finished tables.
+    ///
+    /// Requests which don't fit in the queue are dropped and tried again on the next update.
+    pub fn update(&mut self, params: &HarmonicParams) {
+        if self.requested.as_ref() != Some(params)
+            && self.requests.try_send(Request::Build(*params)).is_ok()
+        {
+            self.requested = Some(*params);
+        }
+        if let Some(old) = self.retired.take() {
+            if let Err(TrySendError::Full(Request::Free(old))) =
+                self.requests.try_send(Request::Free(old))
+            {
+                // Keep it until there's room, rather than freeing it here
+                self.retired = Some(old);
+                return;
+            }
+        }
+        if let Ok(table) = self.tables.get_mut().unwrap().try_recv() {
+            self.retired = self.table.replace(table);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generators_pick_partials() {
+        let params = HarmonicParams {
+            parity: PartialParity::Odd,
+            every: 1,
+            count: 9,
+            ..Default::default()
+        };
+        let kept: Vec<usize> = (1..=12).filter(|&x| params.keeps(x)).collect();
+        assert_eq!(kept, vec![1, 3, 5, 7, 9]);
+
+        let params = HarmonicParams {
+            parity: PartialParity::Even,
+            every: 1,
+            ..Default::default()
+        };
+        let kept: Vec<usize> = (1..=6).filter(|&x| params.keeps(x)).collect();
+        assert_eq!(kept, vec![1, 2, 4, 6]);
+
+        let params = HarmonicParams {
+            every: 3,
+            ..Default::default()
+        };
+        let kept: Vec<usize> = (1..=10).filter(|&x| params.keeps(x)).collect();
+        assert_eq!(kept, vec![1, 4, 7, 10]);
+    }
+
+    #[test]
+    fn default_spectrum_is_a_saw() {
+        let spectrum = HarmonicParams::default().spectrum();
+        let saw = AdditiveOsc::<PARTIALS>::saw();
+        for phase in [0.1, 1.0, 2.5, 4.0] {
+            let difference = spectrum.generate(phase, 64) - saw.generate(phase, 64);
+            assert!(difference.abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn partials_can_be_edited() {
+        let mut params = HarmonicParams {
+            rolloff: 0.0,
+            count: 2,
+            ..Default::default()
+        };
+        params.gains[1] = -6.0;
+        params.phases[1] = 0.25;
+        let spectrum = params.spectrum();
+        let expected = |phase: f32| {
+            let second = 10.0_f32.powf(-6.0 / 20.0);
+            let saw_power: f32 = (1..=PARTIALS).map(|n| (n as f32).powi(-2)).sum();
+            let scale = (saw_power / (1.0 + second * second)).sqrt();
+            scale * (phase.sin() + second * (phase * 2.0 + PI / 2.0).sin())
+        };
+        for phase in [0.1, 1.0, 2.5, 4.0] {
+            assert!((spectrum.generate(phase, 2) - expected(phase)).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn tables_are_built_in_the_background() {
+        let mut builder = HarmonicBuilder::new(48000.0, 2048);
+        let params = HarmonicParams::default();
+        builder.update(&params);
+        for _ in 0..1000 {
+            if builder.table.is_some() {
+                return;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+            builder.update(&params);
+        }
+        panic!("table was never built");
+    }
+
+    #[test]
+    fn requests_dropped_from_a_full_queue_are_retried() {
+        let mut builder = HarmonicBuilder::new(48000.0, 2048);
+        let params = |count| HarmonicParams {
+            count,
+            ..Default::default()
+        };
+        // Far more changes than fit in the queue
+        for count in 1..=QUEUED_REQUESTS * 4 {
+            builder.update(&params(count));
+        }
+        let last = params(QUEUED_REQUESTS * 4);
+        for _ in 0..1000 {
+            if builder.requested == Some(last) {
+                return;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+            builder.update(&last);
+        }
+        panic!("newest parameters were never requested");
+    }
+}
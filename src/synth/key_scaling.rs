@@ -0,0 +1,103 @@
+/// Loudest boost key scaling can give, so extreme settings can't blow up the output.
+const MAX_BOOST_DB: f32 = 24.0;
+
+#[derive(Debug, Clone)]
+/// Keyboard scaling of level and envelope times, relative to a breakpoint key.
+///
+/// Like level and rate scaling on FM synths, this lets high notes decay faster and keeps low
+/// notes from overwhelming the mix on plucked and piano-like patches.
+pub struct KeyScaling {
+    /// MIDI note at which nothing is scaled.
+    pub breakpoint: f32,
+    /// Level change per octave below the breakpoint, in dB. Negative values make lower notes
+    /// quieter.
+    pub low_depth: f32,
+    /// Level change per octave above the breakpoint, in dB. Negative values make higher notes
+    /// quieter.
+    pub high_depth: f32,
+    /// How much envelopes speed up per octave above the breakpoint (and slow down below it).
+    /// At 1, envelope times halve every octave.
+    pub rate: f32,
+}
+impl KeyScaling {
+    fn octaves(&self, note: f32) -> f32 {
+        (note - self.breakpoint) / 12.0
+    }
+    /// Gain multiplier for a voice playing `note`.
+    pub fn amp_mult(&self, note: f32) -> f32 {
+        let octaves = self.octaves(note);
+        let depth = if octaves < 0.0 {
+            self.low_depth
+        } else {
+            self.high_depth
+        };
+        let db = (octaves.abs() * depth).min(MAX_BOOST_DB);
+        10.0_f32.powf(db / 20.0)
+    }
+    /// Multiplier for the speed of a voice's envelopes, i.e. the inverse of the scaling of
+    /// their stage times.
+    pub fn rate_mult(&self, note: f32) -> f32 {
+        (self.octaves(note) * self.rate).exp2()
+    }
+}
+impl Default for KeyScaling {
+    fn default() -> Self {
+        Self {
+            breakpoint: 60.0,
+            low_depth: 0.0,
+            high_depth: 0.0,
+            rate: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(value: f32, expected: f32) {
+        assert!((value - expected).abs() < 1e-4, "{value} != {expected}");
+    }
+
+    #[test]
+    fn breakpoint_is_unscaled() {
+        let scaling = KeyScaling {
+            breakpoint: 48.0,
+            low_depth: -6.0,
+            high_depth: -12.0,
+            rate: 1.0,
+        };
+        assert_eq!(scaling.amp_mult(48.0), 1.0);
+        assert_eq!(scaling.rate_mult(48.0), 1.0);
+    }
+
+    #[test]
+    fn each_side_has_its_own_depth() {
+        let scaling = KeyScaling {
+            low_depth: -6.0,
+            high_depth: -12.0,
+            ..Default::default()
+        };
+        assert_near(scaling.amp_mult(48.0), 10.0_f32.powf(-6.0 / 20.0));
+        assert_near(scaling.amp_mult(84.0), 10.0_f32.powf(-24.0 / 20.0));
+    }
+
+    #[test]
+    fn boost_is_limited() {
+        let scaling = KeyScaling {
+            low_depth: 24.0,
+            ..Default::default()
+        };
+        assert_near(scaling.amp_mult(0.0), 10.0_f32.powf(MAX_BOOST_DB / 20.0));
+    }
+
+    #[test]
+    fn envelopes_speed_up_for_high_notes() {
+        let scaling = KeyScaling {
+            rate: 1.0,
+            ..Default::default()
+        };
+        assert_near(scaling.rate_mult(72.0), 2.0);
+        assert_near(scaling.rate_mult(48.0), 0.5);
+    }
+}
@@ -0,0 +1,299 @@
+use super::lerp;
+
+/// Number of breakpoints exposed as ports.
+pub const MSEG_POINTS: usize = 6;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MsegPoint {
+    /// Level reached at this point, between -1.0 and 1.0.
+    pub level: f32,
+    /// Length of the segment leading to this point, in seconds or beats.
+    pub time: f32,
+    /// 0 is linear, positive moves quickly at first, negative moves slowly at first.
+    pub curve: f32,
+}
+impl Default for MsegPoint {
+    fn default() -> Self {
+        Self {
+            level: 0.0,
+            time: 0.25,
+            curve: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MsegLoop {
+    Off,
+    /// Loops until the note is released.
+    WhileHeld,
+    /// Loops even after release, so the MSEG acts like a complex LFO.
+    Always,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MsegDestination {
+    Off,
+    /// Pitch of one oscillator, or every oscillator if `None`.
+    Pitch(Option<usize>),
+    Amplitude(usize),
+    FilterCutoff,
+    FilterResonance,
+    Volume,
+    /// Wavetable position of one oscillator, or every oscillator if `None`.
+    WavetablePosition(Option<usize>),
+}
+
+#[derive(Debug, Clone)]
+/// Multi-segment envelope generator.
+///
+/// The MSEG starts at 0 and moves towards each point in turn. While held, it stops at the
+/// sustain point (if any) until the note is released, then plays the remaining points. The
+/// points between `loop_start` and `loop_end` can be looped, in which case the MSEG glides
+/// from `loop_end` back to `loop_start` using `loop_start`'s time and curve.
+pub struct Mseg {
+    pub points: [MsegPoint; MSEG_POINTS],
+    /// Number of points in use.
+    pub point_count: usize,
+    pub loop_mode: MsegLoop,
+    pub loop_start: usize,
+    pub loop_end: usize,
+    pub sustain_point: Option<usize>,
+    /// Interpret point times as beats at `bpm` instead of seconds.
+    pub tempo_sync: bool,
+    pub bpm: f32,
+    pub destination: MsegDestination,
+    /// Bipolar modulation depth.
+    pub amount: f32,
+}
+impl Mseg {
+    fn looping(&self, released: bool) -> bool {
+        let mode = match self.loop_mode {
+            MsegLoop::Off => false,
+            MsegLoop::WhileHeld => !released,
+            MsegLoop::Always => true,
+        };
+        mode && self.loop_start <= self.loop_end && self.loop_end < self.point_count
+    }
+    /// Converts seconds into the unit point times are measured in.
+    fn time_scale(&self) -> f32 {
+        if self.tempo_sync {
+            self.bpm / 60.0
+        } else {
+            1.0
+        }
+    }
+    /// Pitch multiplier for the given oscillator, with ±2 octaves at full amount.
+    pub fn pitch_mult(&self, value: f32, oscillator: usize) -> f32 {
+        match self.destination {
+            MsegDestination::Pitch(None) => (value * self.amount * 2.0).exp2(),
+            MsegDestination::Pitch(Some(x)) if x == oscillator => {
+                (value * self.amount * 2.0).exp2()
+            }
+            _ => 1.0,
+        }
+    }
+    /// Amplitude multiplier for the given oscillator. At full amount, the MSEG's level is
+    /// used as the oscillator's gain.
+    pub fn amp_mult(&self, value: f32, oscillator: usize) -> f32 {
+        match self.destination {
+            MsegDestination::Amplitude(x) if x == oscillator => self.gain(value),
+            _ => 1.0,
+        }
+    }
+    /// Filter cutoff multiplier, with ±8 octaves at full amount.
+    pub fn cutoff_mult(&self, value: f32) -> f32 {
+        match self.destination {
+            MsegDestination::FilterCutoff => (value * self.amount * 8.0).exp2(),
+            _ => 1.0,
+        }
+    }
+    /// Resonance offset, sweeping the whole resonance range at full amount.
+    pub fn resonance_offset(&self, value: f32) -> f32 {
+        match self.destination {
+            MsegDestination::FilterResonance => value * self.amount * 10.0,
+            _ => 0.0,
+        }
+    }
+    pub fn volume_mult(&self, value: f32) -> f32 {
+        match self.destination {
+            MsegDestination::Volume => self.gain(value),
+            _ => 1.0,
+        }
+    }
+    /// Wavetable position offset, sweeping the whole table at full amount.
+    pub fn position_offset(&self, value: f32, oscillator: usize) -> f32 {
+        match self.destination {
+            MsegDestination::WavetablePosition(None) => value * self.amount,
+            MsegDestination::WavetablePosition(Some(x)) if x == oscillator => value * self.amount,
+            _ => 0.0,
+        }
+    }
+    fn gain(&self, value: f32) -> f32 {
+        lerp(1.0, value, self.amount).max(0.0)
+    }
+}
+impl Default for Mseg {
+    fn default() -> Self {
+        let mut points = [MsegPoint::default(); MSEG_POINTS];
+        points[0].level = 1.0;
+        Self {
+            points,
+            point_count: 4,
+            loop_mode: MsegLoop::Off,
+            loop_start: 0,
+            loop_end: 1,
+            sustain_point: None,
+            tempo_sync: false,
+            bpm: 120.0,
+            destination: MsegDestination::Off,
+            amount: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Per-voice playback position of an `Mseg`.
+pub struct MsegVoice {
+    /// Index of the point the MSEG is moving towards.
+    segment: usize,
+    /// Time spent in the current segment.
+    elapsed: f32,
+    /// Level when the current segment began, so jumps between segments never click.
+    start_level: f32,
+    level: f32,
+    released: bool,
+}
+impl MsegVoice {
+    /// Starts again from the first segment, gliding from the current level.
+    pub fn restart(&mut self) {
+        self.start_segment(0);
+        self.released = false;
+    }
+    pub fn release(&mut self, mseg: &Mseg) {
+        if self.released {
+            return;
+        }
+        self.released = true;
+        if let Some(sustain) = mseg.sustain_point {
+            if self.segment <= sustain {
+                self.start_segment(sustain + 1);
+            }
+        }
+    }
+    fn start_segment(&mut self, segment: usize) {
+        self.segment = segment;
+        self.elapsed = 0.0;
+        self.start_level = self.level;
+    }
+    /// Advances by `time_step` seconds and returns the new level.
+    pub fn next(&mut self, mseg: &Mseg, time_step: f32) -> f32 {
+        self.elapsed += time_step * mseg.time_scale();
+        // Only zero length segments can finish more than once per sample, so this can't
+        // spin forever as long as the loop contains a segment with some length.
+        for _ in 0..=mseg.point_count {
+            if self.segment >= mseg.point_count {
+                return self.level;
+            }
+            let point = &mseg.points[self.segment];
+            if self.elapsed < point.time {
+                let progress = 1.0 - (1.0 - self.elapsed / point.time).powf(point.curve.exp2());
+                self.level = lerp(self.start_level, point.level, progress);
+                return self.level;
+            }
+
+            // Segment finished
+            self.level = point.level;
+            let held_at_sustain = !self.released && mseg.sustain_point == Some(self.segment);
+            if mseg.looping(self.released) && self.segment == mseg.loop_end {
+                let overshoot = self.elapsed - point.time;
+                self.start_segment(mseg.loop_start);
+                self.elapsed = overshoot;
+            } else if held_at_sustain {
+                self.elapsed = point.time;
+                return self.level;
+            } else {
+                let overshoot = self.elapsed - point.time;
+                self.start_segment(self.segment + 1);
+                self.elapsed = overshoot;
+            }
+        }
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mseg(levels: &[f32]) -> Mseg {
+        let mut mseg = Mseg {
+            point_count: levels.len(),
+            ..Default::default()
+        };
+        for (point, &level) in mseg.points.iter_mut().zip(levels) {
+            *point = MsegPoint {
+                level,
+                time: 1.0,
+                curve: 0.0,
+            };
+        }
+        mseg
+    }
+
+    #[test]
+    fn plays_each_segment_then_holds() {
+        let mseg = mseg(&[1.0, -1.0]);
+        let mut voice = MsegVoice::default();
+        assert_eq!(voice.next(&mseg, 0.5), 0.5);
+        assert_eq!(voice.next(&mseg, 0.5), 1.0);
+        assert_eq!(voice.next(&mseg, 0.5), 0.0);
+        assert_eq!(voice.next(&mseg, 0.5), -1.0);
+        assert_eq!(voice.next(&mseg, 5.0), -1.0);
+    }
+
+    #[test]
+    fn sustains_until_released() {
+        let mut mseg = mseg(&[1.0, 0.5, 0.0]);
+        mseg.sustain_point = Some(1);
+        let mut voice = MsegVoice::default();
+        assert_eq!(voice.next(&mseg, 1.5), 0.75);
+        assert_eq!(voice.next(&mseg, 5.0), 0.5);
+        voice.release(&mseg);
+        assert_eq!(voice.next(&mseg, 0.5), 0.25);
+        assert_eq!(voice.next(&mseg, 0.5), 0.0);
+    }
+
+    #[test]
+    fn release_glides_from_current_level() {
+        let mut mseg = mseg(&[1.0, 0.5, 0.0]);
+        mseg.sustain_point = Some(1);
+        let mut voice = MsegVoice::default();
+        assert_eq!(voice.next(&mseg, 0.5), 0.5);
+        voice.release(&mseg);
+        assert_eq!(voice.next(&mseg, 0.5), 0.25);
+    }
+
+    #[test]
+    fn loops_while_held() {
+        let mut mseg = mseg(&[1.0, 0.0, -1.0]);
+        mseg.loop_mode = MsegLoop::WhileHeld;
+        mseg.loop_start = 0;
+        mseg.loop_end = 1;
+        let mut voice = MsegVoice::default();
+        assert_eq!(voice.next(&mseg, 2.0), 0.0);
+        assert_eq!(voice.next(&mseg, 1.0), 1.0);
+        assert_eq!(voice.next(&mseg, 1.0), 0.0);
+        voice.release(&mseg);
+        assert_eq!(voice.next(&mseg, 3.0), -1.0);
+    }
+
+    #[test]
+    fn tempo_sync_measures_time_in_beats() {
+        let mut mseg = mseg(&[1.0]);
+        mseg.tempo_sync = true;
+        mseg.bpm = 120.0;
+        let mut voice = MsegVoice::default();
+        assert_eq!(voice.next(&mseg, 0.25), 0.5);
+    }
+}
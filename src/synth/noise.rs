@@ -0,0 +1,165 @@
+use super::smoothing::{Smoother, SmoothingBlock};
+
+/// Gains which bring each noise colour to roughly the same RMS level as white noise.
+const PINK_GAIN: f32 = 0.328;
+const BROWN_GAIN: f32 = 10.1;
+const DIGITAL_GAIN: f32 = 0.577;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseType {
+    White,
+    /// Equal energy per octave (-3 dB/oct), which sounds more natural for breath and wind.
+    Pink,
+    /// Random walk (-6 dB/oct), a deep rumble.
+    Brown,
+    /// Output of a 15 bit LFSR clocked at the noise rate, like the noise channel on old
+    /// sound chips.
+    Digital,
+}
+impl NoiseType {
+    pub fn from_index(index: f32) -> Self {
+        match index {
+            x if x < 1.0 => NoiseType::White,
+            x if x < 2.0 => NoiseType::Pink,
+            x if x < 3.0 => NoiseType::Brown,
+            _ => NoiseType::Digital,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Per-voice noise generator state.
+pub struct NoiseVoice {
+    /// Xorshift state. `fastrand::Rng` isn't `Sync`, which plugins must be.
+    rng: u32,
+    /// Filter states for Paul Kellet's pink noise filter.
+    pink: [f32; 7],
+    brown: f32,
+    lfsr: u16,
+    /// Progress towards the next LFSR clock, from 0 to 1.
+    lfsr_phase: f32,
+}
+impl Default for NoiseVoice {
+    fn default() -> Self {
+        Self {
+            // Xorshift gets stuck at 0
+            rng: fastrand::u32(1..),
+            pink: [0.0; 7],
+            brown: 0.0,
+            lfsr: 1,
+            lfsr_phase: 0.0,
+        }
+    }
+}
+impl NoiseVoice {
+    fn white(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        // Top 24 bits, which fit in an f32 exactly
+        (self.rng >> 8) as f32 / (1 << 23) as f32 - 1.0
+    }
+    /// Generates the next sample. `rate` is the digital noise clock in Hz; the other noise
+    /// types ignore it.
+    pub fn next(&mut self, noise: NoiseType, rate: f32, sample_rate: f32) -> f32 {
+        match noise {
+            NoiseType::White => self.white(),
+            NoiseType::Pink => {
+                let white = self.white();
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                pink * PINK_GAIN
+            }
+            NoiseType::Brown => {
+                // Leaky integrator, so the random walk can't drift away from 0
+                self.brown = (self.brown + 0.02 * self.white()) / 1.02;
+                self.brown * BROWN_GAIN
+            }
+            NoiseType::Digital => {
+                self.lfsr_phase += rate / sample_rate;
+                while self.lfsr_phase >= 1.0 {
+                    self.lfsr_phase -= 1.0;
+                    let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+                    self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+                }
+                if self.lfsr & 1 == 1 {
+                    DIGITAL_GAIN
+                } else {
+                    -DIGITAL_GAIN
+                }
+            }
+        }
+    }
+}
+
+/// Controls for the dedicated noise generator. Its `rate` is shared with oscillators
+/// playing digital noise, which take their noise type from their wave.
+pub struct NoiseParams {
+    pub noise_type: NoiseType,
+    pub level: f32,
+    /// Clock rate of digital noise, in Hz.
+    pub rate: f32,
+    level_smoother: Smoother,
+}
+impl NoiseParams {
+    pub fn update_smoothing(&mut self, block: &SmoothingBlock) {
+        self.level_smoother.next_block(self.level, block);
+    }
+    /// Returns the smoothed level `index` samples into the current buffer.
+    #[inline]
+    pub fn smoothed_level(&self, index: usize) -> f32 {
+        self.level_smoother.at(index)
+    }
+}
+impl Default for NoiseParams {
+    fn default() -> Self {
+        Self {
+            noise_type: NoiseType::White,
+            level: 0.0,
+            rate: 8000.0,
+            level_smoother: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(noise: NoiseType, rate: f32) -> f32 {
+        let mut voice = NoiseVoice::default();
+        let samples = 200_000;
+        let sum: f32 = (0..samples)
+            .map(|_| voice.next(noise, rate, 48000.0).powi(2))
+            .sum();
+        (sum / samples as f32).sqrt()
+    }
+
+    #[test]
+    fn colours_are_level_matched() {
+        let white = rms(NoiseType::White, 8000.0);
+        for noise in [NoiseType::Pink, NoiseType::Brown, NoiseType::Digital] {
+            let db = 20.0 * (rms(noise, 8000.0) / white).log10();
+            assert!(db.abs() < 1.0, "{noise:?} is {db} dB from white noise");
+        }
+    }
+
+    #[test]
+    fn lfsr_holds_between_clocks() {
+        let mut voice = NoiseVoice::default();
+        let samples: Vec<f32> = (0..4800)
+            .map(|_| voice.next(NoiseType::Digital, 1000.0, 48000.0))
+            .collect();
+        let changes = samples.windows(2).filter(|x| x[0] != x[1]).count();
+        // At most one change per clock, and the sequence isn't stuck
+        assert!(changes <= 100);
+        assert!(changes > 10);
+    }
+}
@@ -1,7 +1,8 @@
 use std::{
     convert::TryInto,
     f32::consts::{FRAC_1_SQRT_2, PI},
-    iter,
+    fs, iter,
+    path::Path,
 };
 
 use itertools::izip;
@@ -10,7 +11,13 @@ use rustfft::{
     FftPlanner,
 };
 
-use super::lerp;
+use self::blep::{BlepShape, BlepVoice, Reset};
+use super::{
+    harmonics::HarmonicBuilder,
+    lerp,
+    noise::NoiseType,
+    smoothing::{Smoother, SmoothingBlock},
+};
 
 /// A single instance of a playing oscillator. Maintains phase in
 /// internal state
@@ -37,6 +44,19 @@ impl OscVoice {
 /// `add_phase()`.
 pub struct SuperVoice {
     pub voice_phases: [f32; 32],
+    /// Time of each phase's wrap during the last sample, as a fraction of the sample. Used
+    /// by oscillators synced to this one.
+    wraps: [Option<f32>; 32],
+    /// Sync resets during the last sample.
+    resets: [Option<Reset>; 32],
+    /// Direction each phase moves in, which reverses with soft sync.
+    directions: [f32; 32],
+    blep: [BlepVoice; 32],
+    /// Unison voices whose `blep` followed their phase through the last sample. Others are
+    /// resynced before they're used.
+    blep_followed: usize,
+    /// Unison voices whose `blep` has followed their phase through this sample.
+    blep_following: usize,
 }
 impl SuperVoice {
     pub fn new(phase: f32, phase_random: f32) -> Self {
@@ -46,24 +66,138 @@ impl SuperVoice {
         for phase in voice_phases.iter_mut() {
             *phase += rng.f32() * phase_random;
         }
-        Self { voice_phases }
+        Self {
+            voice_phases,
+            wraps: [None; 32],
+            resets: [None; 32],
+            directions: [1.0; 32],
+            blep: voice_phases.map(|phase| BlepVoice::new(phase / (2.0 * PI))),
+            blep_followed: 32,
+            blep_following: 32,
+        }
+    }
+    /// Advances every unison voice's phase, resetting them whenever the matching voice of
+    /// `sync`'s master wraps.
+    pub fn add_phase(
+        &mut self,
+        delta: f32,
+        voice_count: usize,
+        detune: f32,
+        sync: Option<&SyncSource>,
+    ) {
+        self.blep_followed = std::mem::take(&mut self.blep_following);
+        for (i, (phase, wrap, reset, direction)) in izip!(
+            self.voice_phases.iter_mut(),
+            self.wraps.iter_mut(),
+            self.resets.iter_mut(),
+            self.directions.iter_mut(),
+        )
+        .take(voice_count)
+        .enumerate()
+        {
+            let detune_index = i as i32 - ((i % 2) * i * 2) as i32;
+            let delta = delta + delta * detune_index as f32 * detune / (voice_count as f32);
+
+            let mode = sync.map_or(SyncMode::Off, |sync| sync.mode);
+            if mode != SyncMode::Soft {
+                *direction = 1.0;
+            }
+            let increment = delta * *direction;
+            let master_wrap = sync.and_then(|sync| sync.wraps[i % sync.voice_count.max(1)]);
+            let start = *phase;
+
+            *reset = None;
+            *wrap = None;
+            match (mode, master_wrap) {
+                (SyncMode::Hard, Some(time)) => {
+                    *phase = increment * (1.0 - time);
+                    *reset = Some(Reset {
+                        time,
+                        increment_before: increment / (2.0 * PI),
+                        phase: 0.0,
+                        increment_after: increment / (2.0 * PI),
+                    });
+                    // Syncing restarts the cycle, so anything synced to this restarts too
+                    *wrap = Some(time);
+                }
+                (SyncMode::Soft, Some(time)) => {
+                    let at = (start + increment * time).rem_euclid(2.0 * PI);
+                    *direction = -*direction;
+                    *phase = at - increment * (1.0 - time);
+                    *reset = Some(Reset {
+                        time,
+                        increment_before: increment / (2.0 * PI),
+                        phase: at / (2.0 * PI),
+                        increment_after: -increment / (2.0 * PI),
+                    });
+                }
+                _ => {
+                    *phase = start + increment;
+                    if *phase >= 2.0 * PI {
+                        *wrap = Some((2.0 * PI - start) / increment);
+                    } else if *phase < 0.0 {
+                        *wrap = Some(start / -increment);
+                    }
+                }
+            }
+            *phase = phase.rem_euclid(2.0 * PI);
+        }
+    }
+    /// Wraps from the last sample, for oscillators synced to this one with `mode`.
+    pub fn sync_source(&self, mode: SyncMode, voice_count: usize) -> SyncSource {
+        SyncSource {
+            mode,
+            wraps: self.wraps,
+            voice_count,
+        }
     }
-    pub fn add_phase(&mut self, delta: f32, voice_count: usize, detune: f32) {
-        // self.phase = (self.phase + delta) % (2.0 * PI);
-        // self.phase
+    /// Generates `shape` with bandlimited steps from each unison voice's phase. Must be called
+    /// once per sample, after the phases are advanced, since it tracks how they move.
+    pub fn generate_blep(
+        &mut self,
+        shape: BlepShape,
+        voice_count: usize,
+        phase_offset: f32,
+    ) -> impl Iterator<Item = f32> + '_ {
+        let followed = self.blep_followed;
+        self.blep_following = voice_count;
         self.voice_phases
-            .iter_mut()
+            .iter()
+            .zip(self.blep.iter_mut())
             .take(voice_count)
+            .zip(self.resets.iter())
             .enumerate()
-            .for_each(|(i, phase): (usize, &mut f32)| {
-                let i = i as i32 - ((i % 2) * i * 2) as i32;
-                let delta = delta + delta * i as f32 * detune / (voice_count as f32);
-                *phase = (*phase + delta) % (2.0 * PI);
-            });
+            .map(move |(i, ((phase, blep), reset))| {
+                let offset = phase_offset / (2.0 * PI);
+                let phase = (phase / (2.0 * PI) + offset).rem_euclid(1.0);
+                if i >= followed {
+                    *blep = BlepVoice::synced(&shape, phase);
+                    return blep.next(&shape, phase, None);
+                }
+                let reset = reset.map(|reset| Reset {
+                    phase: (reset.phase + offset).rem_euclid(1.0),
+                    ..reset
+                });
+                blep.next(&shape, phase, reset)
+            })
     }
-    pub fn unison_phases(&mut self, delta: f32, voice_count: usize, voices_detune: f32) -> &[f32] {
-        self.add_phase(delta, voice_count, voices_detune);
-        &self.voice_phases
+    /// Stereo version of `generate_blep`, alternating unison voices between the left and
+    /// right channels.
+    pub fn generate_blep_stereo(
+        &mut self,
+        shape: BlepShape,
+        voice_count: usize,
+        phase_offset: f32,
+    ) -> (f32, f32) {
+        self.generate_blep(shape, voice_count, phase_offset)
+            .enumerate()
+            .fold((0.0, 0.0), |(l, r), (i, gen)| {
+                if i % 2 == 0 {
+                    (l + gen, r)
+                } else {
+                    (l, r + gen)
+                }
+            })
     }
     /// TODO: this duplicates all 128 phases, every time.
     pub fn unison_phases_pm(
@@ -73,7 +207,7 @@ impl SuperVoice {
         voices_detune: f32,
         pm: f32,
     ) -> [f32; 32] {
-        self.add_phase(delta, voice_count, voices_detune);
+        self.add_phase(delta, voice_count, voices_detune, None);
         let pm = pm * 150.0;
         let mut out = self.voice_phases;
         for phase in out.iter_mut().take(voice_count) {
@@ -83,6 +217,53 @@ impl SuperVoice {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Oscillator sync, which restarts an oscillator's cycle whenever its master's cycle wraps.
+pub enum SyncMode {
+    Off,
+    /// Jumps back to the start of the cycle, for the classic tearing sync lead sound.
+    Hard,
+    /// Reverses the direction of the cycle instead of jumping, which avoids the sharp edges
+    /// of hard sync for a gentler sound.
+    Soft,
+}
+impl SyncMode {
+    pub fn from_index(index: f32) -> Self {
+        match index {
+            x if x < 1.0 => SyncMode::Off,
+            x if x < 2.0 => SyncMode::Hard,
+            _ => SyncMode::Soft,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// How frequency modulation changes an oscillator's pitch.
+pub enum FmMode {
+    /// Adds to the frequency. Through-zero, so deep modulation runs the phase backwards and
+    /// the carrier stays in tune, which suits FM bells and basses.
+    Linear,
+    /// Moves the pitch in octaves, so it sweeps evenly up and down like vibrato, but drifts
+    /// sharp as it gets deeper.
+    Exponential,
+}
+impl FmMode {
+    pub fn from_index(index: f32) -> Self {
+        match index {
+            x if x < 1.0 => FmMode::Linear,
+            _ => FmMode::Exponential,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// When each unison voice of a sync master wrapped during the last sample.
+pub struct SyncSource {
+    mode: SyncMode,
+    wraps: [Option<f32>; 32],
+    voice_count: usize,
+}
+
 #[derive(Debug, Clone)]
 /// Each voice generating a wave reads OscillatorParams once per sample,
 /// and applies its current parameters to the generated wave.
@@ -101,15 +282,29 @@ pub struct OscillatorParams {
     /// Amp to keep volume roughly equal across different voice counts
     pub unison_amp: f32,
     pub wave: OscWave,
+    pub engine: OscEngine,
+    /// Sync to the next oscillator.
+    pub sync: SyncMode,
+    /// Scan position through a user wavetable's frames, from 0.0 to 1.0.
+    pub wavetable_position: f32,
+    pub wavetable_morph: WavetableMorph,
     pub phase: f32,
     pub phase_rand: f32,
     pub pitch_bend: f32,
 
+    /// Width of the high part of the pulse wave, in radians.
     pub pulse_width: f32,
+    /// Filter envelope modulation of the pulse width, in radians.
+    pub pwm_env: f32,
+    /// Pulse width modulation by the next oscillator.
+    pub pwm_osc: f32,
+    /// Moves the triangle's peak, from -1.0 (falling saw) to 1.0 (rising saw).
+    pub skew: f32,
+    /// Phase modulation by the oscillator's own output.
+    pub feedback: f32,
+    pub fm_mode: FmMode,
 
-    pub fm: f32,
-    pub pm: f32,
-    pub am: f32,
+    smoothers: OscillatorSmoothers,
 }
 impl OscillatorParams {
     fn calc_pitch_mult(&self) -> f32 {
@@ -119,18 +314,128 @@ impl OscillatorParams {
     pub fn semitone_detune(&self) -> f32 {
         self.semitone + self.pitch_bend + self.octave as f32 * 12.0
     }
+    /// Shape to generate with bandlimited steps, if this oscillator needs them.
+    ///
+    /// Wavetables can't follow sync resets or a pulse width which moves every sample, so
+    /// synced oscillators and pulses with a moving width use bandlimited steps instead,
+    /// unless the engine is naive or additive. `lfo_pwm` is whether the LFO modulates this
+    /// oscillator's pulse width. A still pulse stays on the wavetables, which alias less.
+    pub fn blep_shape(&self, smoothed: &SmoothedOscillator, lfo_pwm: bool) -> Option<BlepShape> {
+        let width_moving = lfo_pwm
+            || smoothed.pwm_env != 0.0
+            || smoothed.pwm_osc != 0.0
+            || self.smoothers.pulse_width.is_moving();
+        let needs_blep = self.sync != SyncMode::Off
+            || (matches!(self.wave, OscWave::Pulse { .. }) && width_moving);
+        match self.engine {
+            OscEngine::Wavetable | OscEngine::PolyBlep if needs_blep => {
+                BlepShape::from_wave(&self.wave, smoothed.pulse_width, smoothed.rise())
+            }
+            _ => None,
+        }
+    }
+    /// Two shifted copies of a simpler wave to subtract to make this oscillator's wave, for
+    /// pulses and skewed triangles. `pulse_width` is in radians, including any modulation.
+    pub fn wave_difference(
+        &self,
+        smoothed: &SmoothedOscillator,
+        pulse_width: f32,
+    ) -> Option<WaveDifference> {
+        match self.wave {
+            OscWave::Pulse { .. } => Some(WaveDifference {
+                wave: OscWave::Saw,
+                offsets: (0.0, pulse_width),
+                scale: 1.0,
+            }),
+            OscWave::Tri if smoothed.skew != 0.0 => {
+                let rise = smoothed.rise();
+                Some(WaveDifference {
+                    wave: OscWave::Parabola,
+                    offsets: (-PI * rise, PI * rise),
+                    // Keeps the peaks at the level of the unskewed triangle
+                    scale: 1.0 / (8.0 * rise * (1.0 - rise)),
+                })
+            }
+            _ => None,
+        }
+    }
     pub fn update_total_pitch(&mut self) {
         self.total_multiplier = self.calc_pitch_mult()
     }
-    pub fn total_pitch_multiplier(&self) -> f32 {
-        self.total_multiplier
-    }
     pub fn calc_unison_amp(&self) -> f32 {
         1.0 / (self.voice_count as f32).sqrt()
     }
     pub fn update_unison_amp(&mut self) {
         self.unison_amp = self.calc_unison_amp()
     }
+    /// Moves the smoothed copies of each continuous parameter towards their current values.
+    /// Must be called after `update_total_pitch()`.
+    pub fn update_smoothing(&mut self, block: &SmoothingBlock) {
+        self.smoothers.amp.next_block(self.amp, block);
+        self.smoothers.pitch_multiplier.next_block(self.total_multiplier, block);
+        self.smoothers.pulse_width.next_block(self.pulse_width, block);
+        self.smoothers.pwm_env.next_block(self.pwm_env, block);
+        self.smoothers.pwm_osc.next_block(self.pwm_osc, block);
+        self.smoothers.skew.next_block(self.skew, block);
+        self.smoothers.feedback.next_block(self.feedback, block);
+        self.smoothers
+            .wavetable_position
+            .next_block(self.wavetable_position, block);
+    }
+    /// Returns the smoothed parameters `index` samples into the current buffer.
+    #[inline]
+    pub fn smoothed(&self, index: usize) -> SmoothedOscillator {
+        SmoothedOscillator {
+            amp: self.smoothers.amp.at(index),
+            pitch_multiplier: self.smoothers.pitch_multiplier.at(index),
+            pulse_width: self.smoothers.pulse_width.at(index),
+            pwm_env: self.smoothers.pwm_env.at(index),
+            pwm_osc: self.smoothers.pwm_osc.at(index),
+            skew: self.smoothers.skew.at(index),
+            feedback: self.smoothers.feedback.at(index),
+            wavetable_position: self.smoothers.wavetable_position.at(index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct OscillatorSmoothers {
+    amp: Smoother,
+    pitch_multiplier: Smoother,
+    pulse_width: Smoother,
+    pwm_env: Smoother,
+    pwm_osc: Smoother,
+    skew: Smoother,
+    feedback: Smoother,
+    wavetable_position: Smoother,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Snapshot of an oscillator's smoothed parameters at a single sample.
+pub struct SmoothedOscillator {
+    pub amp: f32,
+    pub pitch_multiplier: f32,
+    pub pulse_width: f32,
+    pub pwm_env: f32,
+    pub pwm_osc: f32,
+    pub skew: f32,
+    pub feedback: f32,
+    pub wavetable_position: f32,
+}
+impl SmoothedOscillator {
+    /// Fraction of the triangle's cycle spent rising. Kept away from 0 and 1, where the
+    /// skewed triangle's scale would blow up.
+    pub fn rise(&self) -> f32 {
+        (0.5 + self.skew * 0.5).clamp(0.01, 0.99)
+    }
+}
+
+/// Wave made by subtracting two shifted copies of `wave`, then scaling by `scale`.
+pub struct WaveDifference {
+    pub wave: OscWave,
+    /// Phase offsets of the copy to subtract from and the copy to subtract, in radians.
+    pub offsets: (f32, f32),
+    pub scale: f32,
 }
 impl Default for OscillatorParams {
     fn default() -> Self {
@@ -144,13 +449,20 @@ impl Default for OscillatorParams {
             voices_detune: 0.1,
             unison_amp: 1.0,
             wave: OscWave::Sine,
+            engine: OscEngine::Wavetable,
+            sync: SyncMode::Off,
+            wavetable_position: 0.0,
+            wavetable_morph: WavetableMorph::Crossfade,
             phase: 0.0,
             phase_rand: PI * 2.0,
             pitch_bend: 0.0,
             pulse_width: PI,
-            fm: 0.0,
-            pm: 0.0,
-            am: 0.0,
+            pwm_env: 0.0,
+            pwm_osc: 0.0,
+            skew: 0.0,
+            feedback: 0.0,
+            fm_mode: FmMode::Linear,
+            smoothers: Default::default(),
         }
     }
 }
@@ -164,6 +476,17 @@ pub enum OscWave {
     Exp,
     Square,
     Pulse { width: f32 },
+    /// Noise needs per-voice state, so it's generated by a `NoiseVoice` instead, and these
+    /// generators output silence.
+    Noise(NoiseType),
+    /// Index into `WavetableSet::user_tables`. These only exist in a `WavetableSet`, so the
+    /// generators here output silence.
+    User(usize),
+    /// The harmonic editor's spectrum, which is also only in a `WavetableSet`.
+    Harmonics,
+    /// Integral of the saw, which skewed triangles are made from in the same way pulses are
+    /// made from saws. It can't be selected, so the generators here output silence.
+    Parabola,
 }
 
 impl OscWave {
@@ -197,6 +520,7 @@ impl OscWave {
                     -FRAC_1_SQRT_2
                 }
             }
+            Noise(_) | User(_) | Harmonics | Parabola => 0.0,
         }
     }
     /// Generates the waveform at the specified phase with all values
@@ -229,6 +553,7 @@ impl OscWave {
                     -1.0
                 }
             }
+            Noise(_) | User(_) | Harmonics | Parabola => 0.0,
         }
     }
     pub fn from_index(index: f32) -> Self {
@@ -238,6 +563,10 @@ impl OscWave {
             x if x < 3.0 => OscWave::Saw,
             x if x < 4.0 => OscWave::Exp,
             x if x < 5.0 => OscWave::Square,
+            x if x < 9.0 => OscWave::Noise(NoiseType::from_index(x - 5.0)),
+            x if x < 10.0 => OscWave::User(0),
+            x if x < 11.0 => OscWave::Harmonics,
+            x if x < 12.0 => OscWave::Pulse { width: PI },
             _ => OscWave::Sine,
         }
     }
@@ -248,20 +577,230 @@ impl OscWave {
             x if x < 3.0 => OscWave::Saw,
             x if x < 4.0 => OscWave::Exp,
             x if x < 5.0 => OscWave::Pulse { width: PI },
+            x if x < 9.0 => OscWave::Noise(NoiseType::from_index(x - 5.0)),
+            x if x < 10.0 => OscWave::User(0),
+            x if x < 11.0 => OscWave::Harmonics,
+            x if x < 12.0 => OscWave::Pulse { width: PI },
             _ => OscWave::Sine,
         }
     }
+    /// Whether `generate_matched` and `generate_polyblep` can generate this wave. Other waves
+    /// only exist as wavetables.
+    pub fn has_matched_shape(&self) -> bool {
+        use OscWave::*;
+
+        matches!(self, Sine | Tri | Saw | Square | Pulse { .. } | Parabola)
+    }
+    /// Generates the waveform with the same shape and level as its bandlimited wavetable, but
+    /// without any bandlimiting.
+    ///
+    /// Like the wavetables, pulse waves are generated as saws and made by subtracting two of
+    /// them.
+    pub fn generate_matched(&self, phase: f32) -> f32 {
+        use OscWave::*;
+
+        match self {
+            Sine => phase.sin(),
+            // The Fourier series of each table sums to these
+            Tri => {
+                let t = phase / (2.0 * PI);
+                let tri = if t < 0.25 {
+                    4.0 * t
+                } else if t < 0.75 {
+                    2.0 - 4.0 * t
+                } else {
+                    4.0 * t - 4.0
+                };
+                tri * PI * PI / 8.0
+            }
+            Saw | Pulse { .. } => (PI - phase) / 2.0,
+            Square => {
+                if phase < PI {
+                    PI / 4.0
+                } else {
+                    -PI / 4.0
+                }
+            }
+            Parabola => PI * PI / 6.0 - PI * phase / 2.0 + phase * phase / 4.0,
+            _ => 0.0,
+        }
+    }
+    /// Like `generate_matched`, but with the steps and corners of the waveform smoothed out by
+    /// polynomial approximations of a bandlimited step (PolyBLEP) and ramp (PolyBLAMP).
+    /// `delta` is the phase increment per sample.
+    pub fn generate_polyblep(&self, phase: f32, delta: f32) -> f32 {
+        use OscWave::*;
+
+        let t = phase / (2.0 * PI);
+        let dt = (delta / (2.0 * PI)).clamp(f32::EPSILON, 0.5);
+        let naive = self.generate_matched(phase);
+        match self {
+            Tri => {
+                // The slope changes by -8 at the peak and +8 at the trough
+                let scale = PI * PI / 8.0;
+                naive + scale * 8.0 * (blamp(t, 0.75, dt) - blamp(t, 0.25, dt))
+            }
+            // Steps up by PI at the end of each cycle
+            Saw | Pulse { .. } => naive + PI * blep(t, 0.0, dt),
+            Square => naive + PI / 2.0 * (blep(t, 0.0, dt) - blep(t, 0.5, dt)),
+            // The slope changes by -2 * PI^2 at the end of each cycle
+            Parabola => naive - 2.0 * PI * PI * blamp(t, 0.0, dt),
+            _ => naive,
+        }
+    }
+    /// Selects which user wavetable to play, if this is a user wavetable.
+    pub fn with_user_table(self, table: usize) -> Self {
+        match self {
+            OscWave::User(_) => OscWave::User(table),
+            wave => wave,
+        }
+    }
 }
 
-pub fn modulate_delta(delta: f32, linear_fm: f32) -> f32 {
-    // `linear_fm` is expected to be between -1.0 and 1.0,
-    // must be stretched out.
-    let linear_fm = linear_fm * 125.0;
+/// Distance from `t` to a discontinuity at `at` in samples, where `t` and `at` are phases
+/// from 0.0 to 1.0 and `dt` is the phase increment per sample.
+fn samples_from(t: f32, at: f32, dt: f32) -> f32 {
+    ((t - at + 0.5).rem_euclid(1.0) - 0.5) / dt
+}
 
-    let delta = delta * (1.0 + linear_fm);
+/// PolyBLEP residual for an upward step of 1 at phase `at`.
+fn blep(t: f32, at: f32, dt: f32) -> f32 {
+    let x = samples_from(t, at, dt);
+    if x <= -1.0 || x >= 1.0 {
+        0.0
+    } else if x < 0.0 {
+        0.5 * (1.0 + x) * (1.0 + x)
+    } else {
+        -0.5 * (1.0 - x) * (1.0 - x)
+    }
+}
 
-    // `rem_euclid()` prevents negative phases, unlike the default modulo
-    delta.rem_euclid(2.0 * PI)
+/// PolyBLAMP residual for an increase in slope of 1 (per cycle) at phase `at`, i.e. the
+/// integral of `blep`.
+fn blamp(t: f32, at: f32, dt: f32) -> f32 {
+    let x = samples_from(t, at, dt).abs();
+    if x >= 1.0 {
+        0.0
+    } else {
+        (1.0 - x).powi(3) / 6.0 * dt
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// How an oscillator generates its waves. Every engine is level matched to the wavetables.
+pub enum OscEngine {
+    /// Waves without bandlimiting, which alias at high pitches for a lo-fi sound.
+    Naive,
+    /// Bandlimited wavetables, which are cheap and alias very little.
+    Wavetable,
+    /// Sums every harmonic below Nyquist each sample. Perfectly alias-free but very slow, so
+    /// it's best kept for offline renders.
+    Additive,
+    /// Naive waves with their discontinuities smoothed by PolyBLEP, which is cheap and sounds
+    /// a little brighter than the wavetables.
+    PolyBlep,
+}
+
+impl OscEngine {
+    pub fn from_index(index: f32) -> Self {
+        match index {
+            x if x < 1.0 => OscEngine::Naive,
+            x if x < 2.0 => OscEngine::Wavetable,
+            x if x < 3.0 => OscEngine::Additive,
+            _ => OscEngine::PolyBlep,
+        }
+    }
+}
+
+/// Generator for an oscillator's wave with a particular engine, from `WavetableSet::generator`.
+pub enum WaveGenerator<'a> {
+    Table(FramePair<'a>),
+    /// The table of the lowest note, which has every harmonic the table can hold.
+    Unfiltered(FramePair<'a>),
+    Naive(&'a OscWave),
+    PolyBlep(&'a OscWave),
+    Additive(&'a AdditiveOsc),
+}
+impl WaveGenerator<'_> {
+    #[inline]
+    fn generate(&self, phase: f32, delta: f32) -> f32 {
+        match self {
+            WaveGenerator::Naive(wave) => wave.generate_matched(phase),
+            WaveGenerator::PolyBlep(wave) => wave.generate_polyblep(phase, delta),
+            WaveGenerator::Additive(osc) => {
+                // Every harmonic below Nyquist
+                let harmonics = (PI / delta.abs().max(f32::EPSILON)) as usize;
+                osc.generate(phase, harmonics)
+            }
+            WaveGenerator::Table(_) | WaveGenerator::Unfiltered(_) => 0.0,
+        }
+    }
+    /// Generates and sums each phase, like `FramePair::generate_multi_pm`.
+    pub fn generate_multi_pm(
+        &self,
+        delta: f32,
+        sample_rate: f32,
+        phases: &[f32],
+        max: usize,
+        phase_offset: f32,
+    ) -> f32 {
+        match self {
+            WaveGenerator::Table(frames) => {
+                frames.generate_multi_pm(delta, sample_rate, phases, max, phase_offset)
+            }
+            WaveGenerator::Unfiltered(frames) => {
+                frames.generate_table_multi_pm(|frame| &frame.tables[0], phases, max, phase_offset)
+            }
+            _ => phases
+                .iter()
+                .take(max)
+                .map(|phase| self.generate((*phase + phase_offset).rem_euclid(2.0 * PI), delta))
+                .sum(),
+        }
+    }
+    /// Stereo version of `generate_multi_pm`.
+    pub fn generate_multi_stereo_pm(
+        &self,
+        delta: f32,
+        sample_rate: f32,
+        phases: &[f32],
+        max: usize,
+        phase_offset: f32,
+    ) -> (f32, f32) {
+        match self {
+            WaveGenerator::Table(frames) => {
+                frames.generate_multi_stereo_pm(delta, sample_rate, phases, max, phase_offset)
+            }
+            WaveGenerator::Unfiltered(frames) => frames.generate_table_multi_stereo_pm(
+                |frame| &frame.tables[0],
+                phases,
+                max,
+                phase_offset,
+            ),
+            _ => phases
+                .iter()
+                .take(max)
+                .map(|phase| self.generate((*phase + phase_offset).rem_euclid(2.0 * PI), delta))
+                .enumerate()
+                .fold((0.0, 0.0), |(l, r), (i, gen)| {
+                    if i % 2 == 0 {
+                        (l + gen, r)
+                    } else {
+                        (l, r + gen)
+                    }
+                }),
+        }
+    }
+}
+
+/// Applies frequency modulation to the phase increment `delta`. Linear FM adds `deviation`
+/// (in radians per sample) and can make the increment negative, while exponential FM moves
+/// the pitch by `octaves`.
+pub fn modulate_delta(delta: f32, mode: FmMode, deviation: f32, octaves: f32) -> f32 {
+    match mode {
+        FmMode::Linear => delta + deviation,
+        FmMode::Exponential => delta * octaves.exp2(),
+    }
 }
 
 /// Efficient constant frequency sine approximation based on 2D rotation.
@@ -566,48 +1105,335 @@ impl WavetableNotes {
     }
 }
 
+/// Frame length of Serum style wavetables, which is assumed for files without a `clm ` chunk
+/// if their length is a multiple of it.
+const SERUM_FRAME_LEN: usize = 2048;
+/// Most frames kept from a user wavetable. Each frame takes about 1 MB once it's expanded into
+/// bandlimited tables, so longer files are thinned out evenly.
+const MAX_USER_FRAMES: usize = 32;
+/// Most in-between frames generated between each pair of frames for spectral morphing. The
+/// total number of in-between frames is also limited to `MAX_USER_FRAMES`.
+const MAX_SPECTRAL_STEPS: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WavetableMorph {
+    /// Blends the samples of neighbouring frames. Harmonics with opposite phases cancel out
+    /// part way between frames.
+    Crossfade,
+    /// Blends the amplitude and phase of each harmonic, so harmonics sweep smoothly between
+    /// frames without cancelling out.
+    Spectral,
+}
+
+/// Wavetable loaded from a WAV file, holding bandlimited tables for each of its frames.
+pub struct UserWavetable {
+    pub name: String,
+    pub frames: Vec<WavetableNotes>,
+    /// Spectrally interpolated frames, `spectral_steps` for each gap between `frames`.
+    spectral_frames: Vec<WavetableNotes>,
+    spectral_steps: usize,
+}
+impl UserWavetable {
+    /// Splits `samples` into single cycle frames of `frame_len`, or treats the whole file as
+    /// one cycle if the frame length isn't known.
+    pub fn from_samples(
+        name: String,
+        samples: &[f32],
+        frame_len: Option<usize>,
+        sample_rate: f32,
+        table_len: usize,
+    ) -> Option<Self> {
+        let frame_len = match frame_len {
+            Some(len) => len,
+            None if samples.len().is_multiple_of(SERUM_FRAME_LEN) => SERUM_FRAME_LEN,
+            None => samples.len(),
+        };
+        let cycles: Vec<&[f32]> = samples.chunks_exact(frame_len.max(1)).collect();
+        if cycles.is_empty() {
+            return None;
+        }
+        let frame_count = cycles.len().min(MAX_USER_FRAMES);
+        let spectra: Vec<AdditiveOsc> = (0..frame_count)
+            .map(|i| AdditiveOsc::from_cycle(cycles[i * cycles.len() / frame_count]))
+            .collect();
+        let build =
+            |osc: &AdditiveOsc| WavetableNotes::from_additive_osc_ifft(osc, sample_rate, table_len);
+
+        let gaps = frame_count - 1;
+        let spectral_steps = MAX_USER_FRAMES
+            .checked_div(gaps)
+            .unwrap_or(0)
+            .min(MAX_SPECTRAL_STEPS);
+        let spectral_frames = spectra
+            .windows(2)
+            .flat_map(|pair| {
+                (1..=spectral_steps).map(move |step| {
+                    pair[0].morph(&pair[1], step as f32 / (spectral_steps + 1) as f32)
+                })
+            })
+            .map(|osc| build(&osc))
+            .collect();
+
+        Some(Self {
+            name,
+            frames: spectra.iter().map(build).collect(),
+            spectral_frames,
+            spectral_steps,
+        })
+    }
+    /// Returns the frames either side of `position` (from 0.0 to 1.0).
+    pub fn frames_at(&self, position: f32, morph: WavetableMorph) -> FramePair<'_> {
+        let stride = match morph {
+            WavetableMorph::Crossfade => 1,
+            WavetableMorph::Spectral => self.spectral_steps + 1,
+        };
+        let last = (self.frames.len() - 1) * stride;
+        let position = position.clamp(0.0, 1.0) * last as f32;
+        let index = (position as usize).min(last.saturating_sub(1));
+        FramePair {
+            from: self.frame(index, stride),
+            to: self.frame((index + 1).min(last), stride),
+            amount: position - index as f32,
+        }
+    }
+    /// Returns frame `index` of the frames and in-between frames interleaved, with `stride`
+    /// frames from one of `frames` to the next.
+    fn frame(&self, index: usize, stride: usize) -> &WavetableNotes {
+        let (frame, step) = (index / stride, index % stride);
+        if step == 0 {
+            &self.frames[frame]
+        } else {
+            &self.spectral_frames[frame * self.spectral_steps + step - 1]
+        }
+    }
+}
+
+/// Two neighbouring wavetable frames, and how far to blend from one to the other.
+pub struct FramePair<'a> {
+    from: &'a WavetableNotes,
+    to: &'a WavetableNotes,
+    amount: f32,
+}
+impl<'a> FramePair<'a> {
+    pub fn single(frame: &'a WavetableNotes) -> Self {
+        Self {
+            from: frame,
+            to: frame,
+            amount: 0.0,
+        }
+    }
+    /// Blends `WavetableNotes::delta_index` followed by `Wavetable::generate_multi_pm` from
+    /// each frame.
+    pub fn generate_multi_pm(
+        &self,
+        delta: f32,
+        sample_rate: f32,
+        phases: &[f32],
+        max: usize,
+        phase_offset: f32,
+    ) -> f32 {
+        self.generate_table_multi_pm(
+            |frame| frame.delta_index(delta, sample_rate),
+            phases,
+            max,
+            phase_offset,
+        )
+    }
+    /// Like `generate_multi_pm`, but reads the table picked by `table` from each frame.
+    fn generate_table_multi_pm(
+        &self,
+        table: impl Fn(&WavetableNotes) -> &Wavetable,
+        phases: &[f32],
+        max: usize,
+        phase_offset: f32,
+    ) -> f32 {
+        let generate =
+            |frame: &WavetableNotes| table(frame).generate_multi_pm(phases, max, phase_offset);
+        let from = generate(self.from);
+        if self.amount == 0.0 {
+            return from;
+        }
+        lerp(from, generate(self.to), self.amount)
+    }
+    /// Stereo version of `generate_multi_pm`.
+    pub fn generate_multi_stereo_pm(
+        &self,
+        delta: f32,
+        sample_rate: f32,
+        phases: &[f32],
+        max: usize,
+        phase_offset: f32,
+    ) -> (f32, f32) {
+        self.generate_table_multi_stereo_pm(
+            |frame| frame.delta_index(delta, sample_rate),
+            phases,
+            max,
+            phase_offset,
+        )
+    }
+    /// Like `generate_multi_stereo_pm`, but reads the table picked by `table` from each frame.
+    fn generate_table_multi_stereo_pm(
+        &self,
+        table: impl Fn(&WavetableNotes) -> &Wavetable,
+        phases: &[f32],
+        max: usize,
+        phase_offset: f32,
+    ) -> (f32, f32) {
+        let generate = |frame: &WavetableNotes| {
+            table(frame).generate_multi_stereo_pm(phases, max, phase_offset)
+        };
+        let from = generate(self.from);
+        if self.amount == 0.0 {
+            return from;
+        }
+        let to = generate(self.to);
+        (
+            lerp(from.0, to.0, self.amount),
+            lerp(from.1, to.1, self.amount),
+        )
+    }
+}
+
 pub struct WavetableSet {
     pub wavetables: Vec<WavetableNotes>,
+    /// Spectra the built in `wavetables` were made from, for the additive engine.
+    pub spectra: Vec<AdditiveOsc>,
+    /// Wavetables loaded from WAV files, sorted by file name.
+    pub user_tables: Vec<UserWavetable>,
+    pub harmonics: HarmonicBuilder,
 }
 impl WavetableSet {
     pub fn new(sample_rate: f32, table_len: usize) -> Self {
+        let spectra = vec![
+            AdditiveOsc::sine(),
+            AdditiveOsc::triangle(),
+            AdditiveOsc::saw(),
+            AdditiveOsc::fake_exp(),
+            AdditiveOsc::square(),
+            AdditiveOsc::parabola(),
+        ];
         Self {
-            wavetables: vec![
-                WavetableNotes::from_additive_osc_ifft(
-                    &AdditiveOsc::sine(),
-                    sample_rate,
-                    table_len,
-                ),
-                WavetableNotes::from_additive_osc_ifft(
-                    &AdditiveOsc::triangle(),
-                    sample_rate,
-                    table_len,
-                ),
-                WavetableNotes::from_additive_osc_ifft(&AdditiveOsc::saw(), sample_rate, table_len),
-                WavetableNotes::from_additive_osc_ifft(
-                    &AdditiveOsc::fake_exp(),
-                    sample_rate,
-                    table_len,
-                ),
-                WavetableNotes::from_additive_osc_ifft(
-                    &AdditiveOsc::square(),
+            wavetables: spectra
+                .iter()
+                .map(|osc| WavetableNotes::from_additive_osc_ifft(osc, sample_rate, table_len))
+                .collect(),
+            spectra,
+            user_tables: Vec::new(),
+            harmonics: HarmonicBuilder::new(sample_rate, table_len),
+        }
+    }
+    /// Loads every `.wav` file in `directory` as a user wavetable, replacing any loaded
+    /// before. Files which can't be read are skipped.
+    pub fn load_user_tables(&mut self, directory: &Path, sample_rate: f32, table_len: usize) {
+        let mut paths: Vec<_> = match fs::read_dir(directory) {
+            Ok(entries) => entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|x| x.eq_ignore_ascii_case("wav"))
+                })
+                .collect(),
+            Err(error) => {
+                println!("couldn't open wavetable folder {directory:?}: {error}");
+                return;
+            }
+        };
+        paths.sort();
+
+        self.user_tables = paths
+            .iter()
+            .filter_map(|path| {
+                let wav = wav::read(path)
+                    .map_err(|error| println!("couldn't load wavetable {path:?}: {error}"))
+                    .ok()?;
+                let name = path.file_stem()?.to_string_lossy().into_owned();
+                UserWavetable::from_samples(
+                    name,
+                    &wav.samples,
+                    wav.frame_len,
                     sample_rate,
                     table_len,
-                ),
-            ],
+                )
+            })
+            .collect();
+        for (i, table) in self.user_tables.iter().enumerate() {
+            println!(
+                "loaded user wavetable {}: {} ({} frames)",
+                i + 1,
+                table.name,
+                table.frames.len()
+            );
+        }
+    }
+    /// Like `select`, but scans through the frames of user wavetables.
+    pub fn select_frames(
+        &self,
+        wave: &OscWave,
+        position: f32,
+        morph: WavetableMorph,
+    ) -> FramePair<'_> {
+        match wave {
+            OscWave::User(index) if *index < self.user_tables.len() => {
+                self.user_tables[*index].frames_at(position, morph)
+            }
+            _ => FramePair::single(self.select(wave)),
+        }
+    }
+    /// Returns a generator for `wave` using `engine`. Waves which can't be made by an engine
+    /// fall back to the wavetables, unfiltered for the naive engine.
+    pub fn generator<'a>(
+        &'a self,
+        engine: OscEngine,
+        wave: &'a OscWave,
+        position: f32,
+        morph: WavetableMorph,
+    ) -> WaveGenerator<'a> {
+        match engine {
+            OscEngine::Naive if wave.has_matched_shape() => WaveGenerator::Naive(wave),
+            OscEngine::Naive => {
+                WaveGenerator::Unfiltered(self.select_frames(wave, position, morph))
+            }
+            OscEngine::PolyBlep if wave.has_matched_shape() => WaveGenerator::PolyBlep(wave),
+            OscEngine::Additive => match Self::wave_index(wave) {
+                Some(index) => WaveGenerator::Additive(&self.spectra[index]),
+                None => WaveGenerator::Table(self.select_frames(wave, position, morph)),
+            },
+            _ => WaveGenerator::Table(self.select_frames(wave, position, morph)),
+        }
+    }
+    /// Index of a built in wave in `wavetables` and `spectra`.
+    fn wave_index(wave: &OscWave) -> Option<usize> {
+        match wave {
+            OscWave::Sine => Some(0),
+            OscWave::Tri => Some(1),
+            OscWave::Saw => Some(2),
+            OscWave::Exp => Some(3),
+            OscWave::Square => Some(4),
+            OscWave::Pulse { width: _ } => Some(2),
+            OscWave::Parabola => Some(5),
+            _ => None,
         }
     }
     pub fn select(&self, wave: &OscWave) -> &WavetableNotes {
-        let wave_index = match wave {
-            OscWave::Sine => 0,
-            OscWave::Tri => 1,
-            OscWave::Saw => 2,
-            OscWave::Exp => 3,
-            OscWave::Square => 4,
-            OscWave::Pulse { width: _ } => 2,
-            _ => 0,
-        };
-        &self.wavetables[wave_index]
+        // Fall back to a sine if a table is missing, e.g. when a preset's file was removed or
+        // the harmonic editor's table is still being built
+        match wave {
+            OscWave::User(index) => {
+                return self
+                    .user_tables
+                    .get(*index)
+                    .map_or(&self.wavetables[0], |table| &table.frames[0]);
+            }
+            OscWave::Harmonics => {
+                return self
+                    .harmonics
+                    .table
+                    .as_deref()
+                    .unwrap_or(&self.wavetables[0]);
+            }
+            _ => {}
+        }
+        &self.wavetables[Self::wave_index(wave).unwrap_or(0)]
     }
 }
 
@@ -621,6 +1447,9 @@ pub struct AdditiveOsc<const N: usize = 2048> {
 }
 
 impl<const N: usize> AdditiveOsc<N> {
+    pub fn new(amplitudes: [f32; N], phases: [f32; N]) -> Self {
+        Self { amplitudes, phases }
+    }
     #[inline]
     pub fn generate(&self, phase: f32, harmonics: usize) -> f32 {
         self.amplitudes
@@ -650,11 +1479,8 @@ impl<const N: usize> AdditiveOsc<N> {
                     .take(harmonics)
                     .zip(self.phases.iter()),
             )
-            // TODO: use phase
-            .map(|(amplitude, _phase)| Complex {
-                re: 0.0,
-                im: *amplitude,
-            })
+            // The inverse FFT sums cosines, and cos(x + phase - PI / 2) = sin(x + phase)
+            .map(|(amplitude, phase)| Complex32::from_polar(*amplitude, phase - PI / 2.0))
             .collect();
         // FFTs must be constant length
         if buffer.len() < N {
@@ -666,6 +1492,57 @@ impl<const N: usize> AdditiveOsc<N> {
             *output = sample.re;
         }
     }
+    /// Analyses a single cycle of a waveform into its harmonics. The DC offset is removed.
+    pub fn from_cycle(cycle: &[f32]) -> Self {
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(cycle.len());
+        let mut buffer: Vec<Complex32> = cycle.iter().map(|&re| Complex { re, im: 0.0 }).collect();
+        fft.process(&mut buffer);
+
+        let mut amplitudes = [0.0; N];
+        let mut phases = [0.0; N];
+        // Bin 0 is DC, and the bins above Nyquist mirror the ones below
+        for (i, (bin, amplitude, phase)) in izip!(
+            buffer.iter().skip(1).take(cycle.len() / 2),
+            amplitudes.iter_mut(),
+            phases.iter_mut(),
+        )
+        .enumerate()
+        {
+            // Every bin except Nyquist shares its energy with its mirror image
+            let scale = if 2 * (i + 1) == cycle.len() { 1.0 } else { 2.0 };
+            *amplitude = bin.norm() * scale / cycle.len() as f32;
+            // The bin describes a cosine, and sin(x + PI / 2) = cos(x)
+            *phase = bin.arg() + PI / 2.0;
+        }
+        Self { amplitudes, phases }
+    }
+    /// Interpolates the amplitude and phase of each harmonic towards `other`'s.
+    pub fn morph(&self, other: &Self, amount: f32) -> Self {
+        let mut amplitudes = [0.0; N];
+        let mut phases = [0.0; N];
+        for (amplitude, phase, from_amp, to_amp, from_phase, to_phase) in izip!(
+            amplitudes.iter_mut(),
+            phases.iter_mut(),
+            self.amplitudes.iter(),
+            other.amplitudes.iter(),
+            self.phases.iter(),
+            other.phases.iter(),
+        ) {
+            *amplitude = lerp(*from_amp, *to_amp, amount);
+            // A silent harmonic's phase means nothing, so use the other one's
+            *phase = if *from_amp == 0.0 {
+                *to_phase
+            } else if *to_amp == 0.0 {
+                *from_phase
+            } else {
+                // Take the shortest way around the circle
+                let difference = (to_phase - from_phase + PI).rem_euclid(2.0 * PI) - PI;
+                from_phase + difference * amount
+            };
+        }
+        Self { amplitudes, phases }
+    }
     pub fn sine() -> Self {
         let mut amplitudes = [0.0; N];
         amplitudes[0] = 1.0;
@@ -712,8 +1589,23 @@ impl<const N: usize> AdditiveOsc<N> {
         let phases = [0.0; N];
         Self { amplitudes, phases }
     }
+    /// Cosines falling at 12 dB/octave, which sum to a parabola between each wrap.
+    pub fn parabola() -> Self {
+        let mut amplitudes = [1.0; N];
+        amplitudes
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, x)| *x /= ((i + 1) as f32).powi(2));
+        let phases = [PI / 2.0; N];
+        Self { amplitudes, phases }
+    }
 }
 
+mod blep;
+
+mod wav;
+
+#[cfg(test)]
 mod tests {
     #[allow(unused_imports)]
     use super::*;
@@ -735,4 +1627,336 @@ mod tests {
         assert!(WavetableNotes::frequency_to_note(440.0) == 69);
         assert!(WavetableNotes::frequency_to_note(415.0) == 68);
     }
+
+    #[test]
+    fn cycles_are_analysed_into_harmonics() {
+        let len = 256;
+        let wave = |phase: f32| 0.3 + (phase * 2.0 + 1.0).sin() * 0.5 - (phase * 5.0).cos() * 0.25;
+        let cycle: Vec<f32> = (0..len)
+            .map(|i| wave(2.0 * PI * i as f32 / len as f32))
+            .collect();
+        let osc = AdditiveOsc::<2048>::from_cycle(&cycle);
+        assert!((osc.amplitudes[1] - 0.5).abs() < 1e-4);
+        assert!((osc.amplitudes[4] - 0.25).abs() < 1e-4);
+
+        // Resynthesising the harmonics gives back the cycle, minus its DC offset
+        for i in 0..len {
+            let phase = 2.0 * PI * i as f32 / len as f32 + 0.1;
+            let expected = wave(phase) - 0.3;
+            assert!((osc.generate(phase, len / 2) - expected).abs() < 1e-3);
+        }
+    }
+
+    /// Checks the IFFT renders `osc` the same as summing its sines directly.
+    fn assert_ifft_matches_sum<const N: usize>(osc: &AdditiveOsc<N>, harmonics: usize) {
+        let mut output = vec![0.0; N];
+        osc.generate_ifft(&mut output, harmonics);
+        for (i, sample) in output.iter().enumerate() {
+            let expected = osc.generate(2.0 * PI * i as f32 / N as f32, harmonics);
+            assert!(
+                (sample - expected).abs() < 1e-3,
+                "sample {i} is {sample}, expected {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn ifft_matches_direct_sum() {
+        assert_ifft_matches_sum(&AdditiveOsc::<256>::sine(), 128);
+        assert_ifft_matches_sum(&AdditiveOsc::<256>::saw(), 40);
+
+        let mut amplitudes = [0.0; 256];
+        let mut phases = [0.0; 256];
+        for (i, (amplitude, phase)) in amplitudes.iter_mut().zip(phases.iter_mut()).enumerate() {
+            *amplitude = 1.0 / (i + 1) as f32;
+            *phase = i as f32 * 0.7;
+        }
+        assert_ifft_matches_sum(&AdditiveOsc::new(amplitudes, phases), 128);
+    }
+
+    #[test]
+    fn ifft_renders_phase_aligned_pulse() {
+        // Cosine partials all peak together, giving a symmetric pulse around phase 0
+        let phases = [PI / 2.0; 64];
+        let osc = AdditiveOsc::new([1.0 / 16.0; 64], phases);
+        assert_ifft_matches_sum(&osc, 16);
+
+        let mut output = vec![0.0; 64];
+        osc.generate_ifft(&mut output, 16);
+        assert!((output[0] - 1.0).abs() < 1e-4);
+        for (left, right) in output[1..32].iter().zip(output[33..].iter().rev()) {
+            assert!((left - right).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn engines_are_level_matched() {
+        let waves = WavetableSet::new(48000.0, 2048);
+        let delta = 2.0 * PI * 110.0 / 48000.0;
+        let rms = |generator: WaveGenerator| {
+            let samples = 48000 / 110 * 4;
+            let sum: f32 = (0..samples)
+                .map(|i| {
+                    let phase = (i as f32 * delta).rem_euclid(2.0 * PI);
+                    generator
+                        .generate_multi_pm(delta, 48000.0, &[phase], 1, 0.0)
+                        .powi(2)
+                })
+                .sum();
+            (sum / samples as f32).sqrt()
+        };
+        for wave in [
+            OscWave::Sine,
+            OscWave::Tri,
+            OscWave::Saw,
+            OscWave::Exp,
+            OscWave::Square,
+            OscWave::Parabola,
+        ] {
+            let generator = |engine| waves.generator(engine, &wave, 0.0, WavetableMorph::Crossfade);
+            let wavetable = rms(generator(OscEngine::Wavetable));
+            for engine in [OscEngine::Naive, OscEngine::Additive, OscEngine::PolyBlep] {
+                let db = 20.0 * (rms(generator(engine)) / wavetable).log10();
+                assert!(db.abs() < 0.5, "{wave:?} with {engine:?} is {db} dB off");
+            }
+        }
+    }
+
+    /// Average phase increment over a cycle of a sine modulator.
+    fn mean_fm_delta(mode: FmMode, depth: f32) -> f32 {
+        let steps = 1000;
+        let sum: f32 = (0..steps)
+            .map(|i| {
+                let modulator = (2.0 * PI * i as f32 / steps as f32).sin();
+                modulate_delta(0.1, mode, modulator * depth * 0.1, modulator * depth)
+            })
+            .sum();
+        sum / steps as f32
+    }
+
+    #[test]
+    fn linear_fm_runs_through_zero() {
+        // Deviation beyond the carrier's frequency runs the phase backwards
+        let delta = modulate_delta(0.1, FmMode::Linear, -0.3, 0.0);
+        assert!((delta + 0.2).abs() < 1e-6);
+        let mut voice = SuperVoice::new(1.0, 0.0);
+        voice.add_phase(delta, 1, 0.0, None);
+        assert!((voice.voice_phases[0] - 0.8).abs() < 1e-6);
+        // Even at a modulation index of 5, the carrier stays in tune on average
+        assert!((mean_fm_delta(FmMode::Linear, 5.0) - 0.1).abs() < 1e-5);
+    }
+
+    #[test]
+    fn exponential_fm_moves_in_octaves() {
+        assert!((modulate_delta(0.1, FmMode::Exponential, 0.0, 1.0) - 0.2).abs() < 1e-6);
+        assert!((modulate_delta(0.1, FmMode::Exponential, 0.0, -2.0) - 0.025).abs() < 1e-6);
+        // Going up an octave adds more than going down one takes away, so it drifts sharp
+        assert!(mean_fm_delta(FmMode::Exponential, 1.0) > 0.11);
+    }
+
+    #[test]
+    fn skewed_triangles_keep_their_level() {
+        let skewed = |skew: f32, phase: f32| {
+            let params = OscillatorParams {
+                wave: OscWave::Tri,
+                ..Default::default()
+            };
+            let smoothed = SmoothedOscillator {
+                skew,
+                ..params.smoothed(0)
+            };
+            let difference = params.wave_difference(&smoothed, PI).unwrap();
+            let generate = |offset: f32| {
+                difference
+                    .wave
+                    .generate_matched((phase + offset).rem_euclid(2.0 * PI))
+            };
+            (generate(difference.offsets.0) - generate(difference.offsets.1)) * difference.scale
+        };
+        // Barely skewed, it's the usual triangle
+        for phase in [0.1, 1.0, 2.5, 4.0, 6.0] {
+            let difference = skewed(1e-6, phase) - OscWave::Tri.generate_matched(phase);
+            assert!(difference.abs() < 1e-3, "{difference} at {phase}");
+        }
+        // Peaks move with the skew, but stay at the triangle's level
+        for skew in [-0.8, -0.3, 0.4, 0.9] {
+            let rise = 0.5 + skew * 0.5;
+            let peak = skewed(skew, PI * rise);
+            let trough = skewed(skew, 2.0 * PI - PI * rise);
+            assert!((peak - PI * PI / 8.0).abs() < 1e-3, "{skew}: {peak}");
+            assert!((trough + PI * PI / 8.0).abs() < 1e-3, "{skew}: {trough}");
+        }
+    }
+
+    #[test]
+    fn polyblep_smooths_steps() {
+        let delta = 2.0 * PI * 3000.0 / 48000.0;
+        // The largest jump between samples of a high saw
+        let largest_step = |generate: &dyn Fn(f32) -> f32| {
+            let samples: Vec<f32> = (0..100)
+                .map(|i| generate((i as f32 * delta + 0.1).rem_euclid(2.0 * PI)))
+                .collect();
+            samples
+                .windows(2)
+                .map(|x| (x[1] - x[0]).abs())
+                .fold(0.0, f32::max)
+        };
+        let naive = largest_step(&|phase| OscWave::Saw.generate_matched(phase));
+        let polyblep = largest_step(&|phase| OscWave::Saw.generate_polyblep(phase, delta));
+        assert!(
+            polyblep < naive * 0.75,
+            "{polyblep} isn't smoother than {naive}"
+        );
+
+        // Far from the step, PolyBLEP leaves the wave alone
+        assert_eq!(
+            OscWave::Saw.generate_polyblep(PI, delta),
+            OscWave::Saw.generate_matched(PI)
+        );
+    }
+
+    #[test]
+    fn hard_sync_resets_when_the_master_wraps() {
+        let mut master = SuperVoice::new(0.0, 0.0);
+        let mut slave = SuperVoice::new(0.0, 0.0);
+        let (master_delta, slave_delta) = (2.0 * PI * 0.1, 2.0 * PI * 0.27);
+        for _ in 0..9 {
+            master.add_phase(master_delta, 1, 0.0, None);
+            let sync = master.sync_source(SyncMode::Hard, 1);
+            slave.add_phase(slave_delta, 1, 0.0, Some(&sync));
+            assert!(slave.resets[0].is_none());
+        }
+        // The master wraps a quarter of the way through the tenth sample
+        master.voice_phases[0] = 2.0 * PI * 0.975;
+        master.add_phase(master_delta, 1, 0.0, None);
+        assert!((master.wraps[0].unwrap() - 0.25).abs() < 1e-4);
+
+        let sync = master.sync_source(SyncMode::Hard, 1);
+        slave.add_phase(slave_delta, 1, 0.0, Some(&sync));
+        let reset = slave.resets[0].unwrap();
+        assert!((reset.time - 0.25).abs() < 1e-4);
+        assert_eq!(reset.phase, 0.0);
+        assert!((slave.voice_phases[0] - slave_delta * 0.75).abs() < 1e-4);
+    }
+
+    #[test]
+    fn only_moving_pulses_use_bandlimited_steps() {
+        let mut params = OscillatorParams {
+            wave: OscWave::Pulse { width: PI },
+            engine: OscEngine::Wavetable,
+            ..Default::default()
+        };
+        let block = crate::synth::smoothing::SmoothingParams::default().block(64, 48000.0);
+        params.update_smoothing(&block);
+        assert_eq!(params.blep_shape(&params.smoothed(0), false), None);
+        assert!(params.blep_shape(&params.smoothed(0), true).is_some());
+
+        params.pwm_env = 0.5;
+        params.update_smoothing(&block);
+        assert!(params.blep_shape(&params.smoothed(0), false).is_some());
+
+        // Turning the width itself ramps it, so it moves until it gets there
+        params.pwm_env = 0.0;
+        params.pulse_width = PI / 2.0;
+        for _ in 0..1000 {
+            params.update_smoothing(&block);
+        }
+        assert_eq!(params.blep_shape(&params.smoothed(0), false), None);
+        params.pulse_width = PI;
+        params.update_smoothing(&block);
+        assert!(params.blep_shape(&params.smoothed(0), false).is_some());
+    }
+
+    #[test]
+    fn bandlimited_steps_pick_up_where_the_phase_is() {
+        let mut voice = SuperVoice::new(0.0, 0.0);
+        let delta = 2.0 * PI * 0.013;
+        voice.add_phase(delta, 1, 0.0, None);
+        voice.generate_blep(BlepShape::Saw, 1, 0.0).for_each(drop);
+        // Played without bandlimited steps for a while, e.g. with a different wave
+        for _ in 0..37 {
+            voice.add_phase(delta, 1, 0.0, None);
+        }
+        voice.add_phase(delta, 1, 0.0, None);
+        let output: f32 = voice.generate_blep(BlepShape::Saw, 1, 0.0).sum();
+        let expected = OscWave::Saw.generate_matched(voice.voice_phases[0]);
+        assert!((output - expected).abs() < 0.05, "{output} != {expected}");
+    }
+
+    #[test]
+    fn soft_sync_reverses() {
+        let mut master = SuperVoice::new(2.0 * PI * 0.95, 0.0);
+        let mut slave = SuperVoice::new(1.0, 0.0);
+        let delta = 2.0 * PI * 0.1;
+        master.add_phase(delta, 1, 0.0, None);
+        let sync = master.sync_source(SyncMode::Soft, 1);
+        slave.add_phase(0.2, 1, 0.0, Some(&sync));
+        // Forwards for half the sample, then backwards
+        assert!((slave.voice_phases[0] - 1.0).abs() < 1e-4);
+        let reset = slave.resets[0].unwrap();
+        assert!(reset.increment_after < 0.0);
+
+        master.add_phase(delta, 1, 0.0, None);
+        let sync = master.sync_source(SyncMode::Soft, 1);
+        slave.add_phase(0.2, 1, 0.0, Some(&sync));
+        assert!((slave.voice_phases[0] - 0.8).abs() < 1e-4);
+    }
+
+    #[test]
+    fn serum_tables_are_split_into_frames() {
+        let samples = vec![0.0; SERUM_FRAME_LEN * 3];
+        let table = UserWavetable::from_samples(String::new(), &samples, None, 48000.0, 2048);
+        assert_eq!(table.unwrap().frames.len(), 3);
+
+        let samples = vec![0.0; 600];
+        let table = UserWavetable::from_samples(String::new(), &samples, None, 48000.0, 2048);
+        assert_eq!(table.unwrap().frames.len(), 1);
+
+        let table = UserWavetable::from_samples(String::new(), &[], None, 48000.0, 2048);
+        assert!(table.is_none());
+    }
+
+    #[test]
+    fn spectral_morph_keeps_harmonics_in_phase() {
+        let cycle = |offset: f32| -> Vec<f32> {
+            (0..64)
+                .map(|i| (2.0 * PI * i as f32 / 64.0 + offset).sin())
+                .collect()
+        };
+        let from = AdditiveOsc::<2048>::from_cycle(&cycle(0.0));
+        let to = AdditiveOsc::<2048>::from_cycle(&cycle(PI));
+        // Crossfading opposite phases would cancel out, but the morph just moves the phase
+        let halfway = from.morph(&to, 0.5);
+        assert!((halfway.amplitudes[0] - 1.0).abs() < 1e-4);
+        let peak = (0..64)
+            .map(|i| halfway.generate(2.0 * PI * i as f32 / 64.0, 1).abs())
+            .fold(0.0, f32::max);
+        assert!((peak - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn position_scans_through_frames() {
+        let samples: Vec<f32> = (0..SERUM_FRAME_LEN * 2)
+            .map(|i| (2.0 * PI * i as f32 / SERUM_FRAME_LEN as f32).sin())
+            .collect();
+        let table =
+            UserWavetable::from_samples(String::new(), &samples, None, 48000.0, 2048).unwrap();
+        let steps = table.spectral_steps;
+        assert_eq!(steps, MAX_SPECTRAL_STEPS);
+        assert_eq!(table.spectral_frames.len(), steps);
+
+        let pair = table.frames_at(0.25, WavetableMorph::Crossfade);
+        assert!(std::ptr::eq(pair.from, &table.frames[0]));
+        assert!(std::ptr::eq(pair.to, &table.frames[1]));
+        assert_eq!(pair.amount, 0.25);
+
+        let pair = table.frames_at(1.0, WavetableMorph::Crossfade);
+        assert!(std::ptr::eq(pair.to, &table.frames[1]));
+        assert_eq!(pair.amount, 1.0);
+
+        // Halfway through the in-between frames
+        let pair = table.frames_at(0.5, WavetableMorph::Spectral);
+        assert!(std::ptr::eq(pair.from, &table.spectral_frames[steps / 2]));
+        assert_eq!(pair.amount, 0.0);
+    }
 }
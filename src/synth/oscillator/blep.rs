@@ -0,0 +1,343 @@
+use std::f32::consts::PI;
+
+use super::OscWave;
+
+/// Point in a waveform where it steps or bends.
+#[derive(Debug, Clone, Copy, Default)]
+struct Breakpoint {
+    /// Phase of the breakpoint, from 0.0 to 1.0.
+    at: f32,
+    /// Jump in value when passing the breakpoint forwards.
+    step: f32,
+    /// Change in slope (per cycle) when passing the breakpoint forwards.
+    bend: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Waveform made of smooth pieces which `BlepVoice` can bandlimit, at the same level as the
+/// matching wavetable.
+pub enum BlepShape {
+    Sine,
+    Saw,
+    Square,
+    /// Saw minus a saw `width` cycles ahead, like the wavetable pulse.
+    Pulse {
+        width: f32,
+    },
+    /// Triangle spending `rise` of its cycle rising, with its peak at `rise / 2`.
+    Tri {
+        rise: f32,
+    },
+}
+impl BlepShape {
+    /// Returns the shape for `wave`, if it has one. `pulse_width` is in radians and `rise` is
+    /// the fraction of a triangle's cycle spent rising.
+    pub fn from_wave(wave: &OscWave, pulse_width: f32, rise: f32) -> Option<Self> {
+        match wave {
+            OscWave::Sine => Some(BlepShape::Sine),
+            OscWave::Saw => Some(BlepShape::Saw),
+            OscWave::Square => Some(BlepShape::Square),
+            OscWave::Pulse { .. } => Some(BlepShape::Pulse {
+                width: (pulse_width / (2.0 * PI)).clamp(0.0, 1.0),
+            }),
+            OscWave::Tri => Some(BlepShape::Tri { rise }),
+            _ => None,
+        }
+    }
+    /// Value at phase `t`. At a step, this is the value after the step.
+    fn value(&self, t: f32) -> f32 {
+        match *self {
+            BlepShape::Sine => (2.0 * PI * t).sin(),
+            BlepShape::Saw => PI / 2.0 * (1.0 - 2.0 * t),
+            BlepShape::Square => {
+                if t < 0.5 {
+                    PI / 4.0
+                } else {
+                    -PI / 4.0
+                }
+            }
+            BlepShape::Pulse { width } => {
+                if t < 1.0 - width {
+                    PI * width
+                } else {
+                    PI * (width - 1.0)
+                }
+            }
+            BlepShape::Tri { rise } => {
+                let tri = if t < rise / 2.0 {
+                    2.0 * t / rise
+                } else if t < 1.0 - rise / 2.0 {
+                    1.0 - (2.0 * t - rise) / (1.0 - rise)
+                } else {
+                    2.0 * (t - 1.0) / rise
+                };
+                tri * PI * PI / 8.0
+            }
+        }
+    }
+    /// Slope at phase `t`, per cycle.
+    fn slope(&self, t: f32) -> f32 {
+        match self {
+            BlepShape::Sine => 2.0 * PI * (2.0 * PI * t).cos(),
+            BlepShape::Saw => -PI,
+            BlepShape::Square | BlepShape::Pulse { .. } => 0.0,
+            BlepShape::Tri { rise } if (rise / 2.0..1.0 - rise / 2.0).contains(&t) => {
+                -PI * PI / 4.0 / (1.0 - rise)
+            }
+            BlepShape::Tri { rise } => PI * PI / 4.0 / rise,
+        }
+    }
+    fn breakpoints(&self) -> impl Iterator<Item = Breakpoint> {
+        let step = |at, step| Breakpoint {
+            at,
+            step,
+            bend: 0.0,
+        };
+        let (breakpoints, count) = match *self {
+            BlepShape::Sine => ([Breakpoint::default(); 2], 0),
+            BlepShape::Saw => ([step(0.0, PI), Breakpoint::default()], 1),
+            BlepShape::Square => ([step(0.0, PI / 2.0), step(0.5, -PI / 2.0)], 2),
+            BlepShape::Pulse { width } => ([step(0.0, PI), step(1.0 - width, -PI)], 2),
+            BlepShape::Tri { rise } => {
+                let bend = |at, bend| Breakpoint {
+                    at,
+                    step: 0.0,
+                    bend,
+                };
+                let change = PI * PI / 4.0 / (rise * (1.0 - rise));
+                (
+                    [bend(rise / 2.0, -change), bend(1.0 - rise / 2.0, change)],
+                    2,
+                )
+            }
+        };
+        breakpoints.into_iter().take(count)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Jump to a new phase partway through a sample, e.g. from oscillator sync.
+pub struct Reset {
+    /// Time of the reset after the previous sample, as a fraction of a sample.
+    pub time: f32,
+    /// Phase increment per sample before the reset.
+    pub increment_before: f32,
+    /// Phase to jump to, from 0.0 to 1.0.
+    pub phase: f32,
+    /// Phase increment per sample after the reset, which can change direction.
+    pub increment_after: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Corrections to the previous and current samples for discontinuities between them.
+struct Corrections {
+    before: f32,
+    after: f32,
+}
+impl Corrections {
+    /// Smooths a jump of `height` happening `distance` samples before the current sample,
+    /// with a two sample polynomial approximation of a bandlimited step (PolyBLEP).
+    fn step(&mut self, height: f32, distance: f32) {
+        self.before += height * 0.5 * distance * distance;
+        self.after -= height * 0.5 * (1.0 - distance) * (1.0 - distance);
+    }
+    /// Smooths a change in slope of `change` per sample with a PolyBLAMP, the integral of the
+    /// PolyBLEP.
+    fn bend(&mut self, change: f32, distance: f32) {
+        self.before += change * distance.powi(3) / 6.0;
+        self.after += change * (1.0 - distance).powi(3) / 6.0;
+    }
+    /// Finds the breakpoints of `shape` passed when moving from phase `start` by `increment`
+    /// per sample for `duration` samples, ending `end` samples before the current sample.
+    fn segment(&mut self, shape: &BlepShape, start: f32, increment: f32, duration: f32, end: f32) {
+        let length = (increment * duration).abs();
+        for breakpoint in shape.breakpoints() {
+            let (distance, step) = if increment >= 0.0 {
+                ((breakpoint.at - start).rem_euclid(1.0), breakpoint.step)
+            } else {
+                ((start - breakpoint.at).rem_euclid(1.0), -breakpoint.step)
+            };
+            if distance > 0.0 && distance <= length {
+                let since = end + duration - distance / increment.abs();
+                self.step(step, since);
+                self.bend(breakpoint.bend * increment.abs(), since);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Bandlimited step oscillator state for a single unison voice.
+///
+/// Unlike the wavetables and `OscWave::generate_polyblep`, this can smooth discontinuities
+/// anywhere in a sample, so it can follow sync resets and pulse widths which move every
+/// sample. Output is delayed by one sample so both sides of a discontinuity can be corrected.
+pub struct BlepVoice {
+    /// Phase at the previous sample, from 0.0 to 1.0.
+    phase: f32,
+    /// Previous sample, including the corrections found so far.
+    delayed: f32,
+}
+impl BlepVoice {
+    pub fn new(phase: f32) -> Self {
+        Self {
+            phase: phase.rem_euclid(1.0),
+            delayed: 0.0,
+        }
+    }
+    /// Starts following `shape` from `phase`, as if it had always been there. Used when an
+    /// oscillator switches to bandlimited steps, since the voice hasn't followed its phase.
+    pub fn synced(shape: &BlepShape, phase: f32) -> Self {
+        let phase = phase.rem_euclid(1.0);
+        Self {
+            phase,
+            delayed: shape.value(phase),
+        }
+    }
+    /// Moves to `phase` (from 0.0 to 1.0) and returns the previous sample.
+    ///
+    /// Without a reset, the phase is assumed to have moved the shortest way from the last
+    /// sample, which follows phase modulation too. With a reset, the increments in `reset`
+    /// are used instead.
+    pub fn next(&mut self, shape: &BlepShape, phase: f32, reset: Option<Reset>) -> f32 {
+        let mut corrections = Corrections::default();
+        match reset {
+            None => {
+                let increment = (phase - self.phase + 0.5).rem_euclid(1.0) - 0.5;
+                corrections.segment(shape, self.phase, increment, 1.0, 0.0);
+            }
+            Some(reset) => {
+                let since = 1.0 - reset.time;
+                corrections.segment(shape, self.phase, reset.increment_before, reset.time, since);
+                let from = (self.phase + reset.increment_before * reset.time).rem_euclid(1.0);
+                corrections.step(shape.value(reset.phase) - shape.value(from), since);
+                corrections.bend(
+                    shape.slope(reset.phase) * reset.increment_after
+                        - shape.slope(from) * reset.increment_before,
+                    since,
+                );
+                corrections.segment(shape, reset.phase, reset.increment_after, since, 0.0);
+            }
+        }
+        self.phase = phase;
+
+        let output = self.delayed + corrections.before;
+        self.delayed = shape.value(phase) + corrections.after;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a voice at `increment` cycles per sample, starting from phase 0.
+    fn run(shape: BlepShape, increment: f32, samples: usize) -> Vec<f32> {
+        let mut voice = BlepVoice::new(0.0);
+        (1..=samples)
+            .map(|i| voice.next(&shape, (i as f32 * increment).rem_euclid(1.0), None))
+            .collect()
+    }
+
+    #[test]
+    fn smooth_parts_are_delayed_but_unchanged() {
+        let shape = BlepShape::Saw;
+        let output = run(shape, 0.01, 50);
+        for (i, sample) in output.iter().enumerate().skip(2) {
+            assert!((sample - shape.value(i as f32 * 0.01)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn steps_are_smoothed() {
+        let largest_step = |output: &[f32]| {
+            output
+                .windows(2)
+                .map(|x| (x[1] - x[0]).abs())
+                .fold(0.0, f32::max)
+        };
+        let increment = 3000.0 / 48000.0 + 0.001;
+        for shape in [
+            BlepShape::Saw,
+            BlepShape::Square,
+            BlepShape::Pulse { width: 0.3 },
+        ] {
+            let naive: Vec<f32> = (0..200)
+                .map(|i| shape.value((i as f32 * increment).rem_euclid(1.0)))
+                .collect();
+            let blep = run(shape, increment, 200);
+            assert!(
+                largest_step(&blep) < largest_step(&naive) * 0.75,
+                "{shape:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn shapes_match_the_wavetables() {
+        // A half width pulse is a square wave, at the level of the saws it's made from
+        let pulse = BlepShape::Pulse { width: 0.5 };
+        for t in [0.1, 0.4, 0.6, 0.9] {
+            assert_eq!(pulse.value(t), BlepShape::Square.value(t) * 2.0);
+        }
+        // Skewed triangles are the difference of two parabolas a `rise` cycle apart
+        for rise in [0.1, 0.5, 0.7] {
+            let shape = BlepShape::Tri { rise };
+            for t in [0.02, 0.3, 0.5, 0.8, 0.99] {
+                let parabola =
+                    |t: f32| OscWave::Parabola.generate_matched(t.rem_euclid(1.0) * 2.0 * PI);
+                let expected = (parabola(t - rise / 2.0) - parabola(t + rise / 2.0))
+                    / (8.0 * rise * (1.0 - rise));
+                assert!((shape.value(t) - expected).abs() < 1e-4, "{rise} at {t}");
+            }
+        }
+    }
+
+    #[test]
+    fn corrections_cancel_out_over_a_step() {
+        // A step half way between samples is corrected equally and oppositely either side
+        let mut corrections = Corrections::default();
+        corrections.step(2.0, 0.5);
+        assert_eq!(corrections.before, -corrections.after);
+    }
+
+    #[test]
+    fn resets_are_smoothed() {
+        let shape = BlepShape::Saw;
+        let increment = 0.01;
+        let mut voice = BlepVoice::new(0.5);
+        voice.next(&shape, 0.51, None);
+        // Sync back to phase 0 three quarters of the way to the next sample
+        let before = voice.next(
+            &shape,
+            0.0025,
+            Some(Reset {
+                time: 0.75,
+                increment_before: increment,
+                phase: 0.0,
+                increment_after: increment,
+            }),
+        );
+        let after = voice.next(&shape, 0.0125, None);
+        // The naive saw jumps from about 0 to PI / 2, the smoothed one eases between them
+        let naive_before = shape.value(0.51);
+        let naive_after = shape.value(0.0025);
+        assert!(before > naive_before && before < naive_after);
+        assert!(after > before && after < naive_after);
+
+        // Resetting to where the phase already is changes nothing
+        let mut voice = BlepVoice::new(0.2);
+        voice.next(&shape, 0.21, None);
+        let output = voice.next(
+            &shape,
+            0.22,
+            Some(Reset {
+                time: 0.5,
+                increment_before: increment,
+                phase: 0.215,
+                increment_after: increment,
+            }),
+        );
+        assert!((output - shape.value(0.21)).abs() < 1e-5);
+    }
+}
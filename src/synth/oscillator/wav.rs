@@ -0,0 +1,207 @@
+use std::{fmt, fs, io, path::Path};
+
+#[derive(Debug)]
+pub enum WavError {
+    Io(io::Error),
+    /// The file isn't a RIFF WAVE file, or a chunk runs past the end of the file.
+    Malformed,
+    /// Sample formats other than PCM or 32 bit float.
+    UnsupportedFormat {
+        format: u16,
+        bits: u16,
+    },
+}
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WavError::Io(error) => write!(f, "{error}"),
+            WavError::Malformed => write!(f, "not a valid WAV file"),
+            WavError::UnsupportedFormat { format, bits } => {
+                write!(f, "unsupported sample format {format} with {bits} bits")
+            }
+        }
+    }
+}
+impl From<io::Error> for WavError {
+    fn from(error: io::Error) -> Self {
+        WavError::Io(error)
+    }
+}
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WavFile {
+    /// Samples mixed down to mono, between -1.0 and 1.0.
+    pub samples: Vec<f32>,
+    /// Frame length given by a Serum style `clm ` chunk, if the file has one.
+    pub frame_len: Option<usize>,
+}
+
+/// Reads a WAV file for use as a wavetable.
+///
+/// Only the parts of the format needed for wavetables are supported: 8, 16, 24 and 32 bit
+/// integer PCM and 32 bit float samples, mixed down to mono.
+pub fn read(path: &Path) -> Result<WavFile, WavError> {
+    parse(&fs::read(path)?)
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+pub fn parse(bytes: &[u8]) -> Result<WavFile, WavError> {
+    if bytes.get(0..4) != Some(b"RIFF") || bytes.get(8..12) != Some(b"WAVE") {
+        return Err(WavError::Malformed);
+    }
+    let mut format = None;
+    let mut data = None;
+    let mut frame_len = None;
+
+    let mut offset = 12;
+    while let (Some(id), Some(len)) = (bytes.get(offset..offset + 4), u32_at(bytes, offset + 4)) {
+        let start = offset + 8;
+        let chunk = bytes
+            .get(start..start + len as usize)
+            .ok_or(WavError::Malformed)?;
+        match id {
+            b"fmt " => {
+                let mut tag = u16_at(chunk, 0).ok_or(WavError::Malformed)?;
+                let channels = u16_at(chunk, 2).ok_or(WavError::Malformed)?;
+                let bits = u16_at(chunk, 14).ok_or(WavError::Malformed)?;
+                if tag == FORMAT_EXTENSIBLE {
+                    // The real format is the start of the sub-format GUID
+                    tag = u16_at(chunk, 24).ok_or(WavError::Malformed)?;
+                }
+                format = Some((tag, channels.max(1), bits));
+            }
+            b"data" => data = Some(chunk),
+            // Serum writes "<!>2048 ..." here, giving the frame length
+            b"clm " => {
+                frame_len = std::str::from_utf8(chunk)
+                    .ok()
+                    .and_then(|text| text.strip_prefix("<!>"))
+                    .and_then(|text| text.split(|x: char| !x.is_ascii_digit()).next())
+                    .and_then(|digits| digits.parse().ok())
+                    .filter(|&len| len > 0);
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        offset = start + len as usize + (len as usize % 2);
+    }
+
+    let (tag, channels, bits) = format.ok_or(WavError::Malformed)?;
+    let data = data.ok_or(WavError::Malformed)?;
+    let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+        (FORMAT_PCM, 8) => |x| (x[0] as f32 - 128.0) / 128.0,
+        (FORMAT_PCM, 16) => |x| i16::from_le_bytes([x[0], x[1]]) as f32 / 32768.0,
+        (FORMAT_PCM, 24) => |x| i32::from_le_bytes([0, x[0], x[1], x[2]]) as f32 / 2147483648.0,
+        (FORMAT_PCM, 32) => |x| i32::from_le_bytes([x[0], x[1], x[2], x[3]]) as f32 / 2147483648.0,
+        (FORMAT_FLOAT, 32) => |x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]),
+        (format, bits) => return Err(WavError::UnsupportedFormat { format, bits }),
+    };
+    let sample_size = bits as usize / 8;
+    let samples = data
+        .chunks_exact(sample_size * channels as usize)
+        .map(|frame| frame.chunks_exact(sample_size).map(decode).sum::<f32>() / channels as f32)
+        .collect();
+
+    Ok(WavFile { samples, frame_len })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a WAV file out of `chunks`.
+    fn riff(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for (id, chunk) in chunks {
+            body.extend_from_slice(*id);
+            body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            body.extend_from_slice(chunk);
+            if chunk.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        file.extend(body);
+        file
+    }
+
+    fn fmt(tag: u16, channels: u16, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        [
+            &tag.to_le_bytes()[..],
+            &channels.to_le_bytes(),
+            &48000_u32.to_le_bytes(),
+            &(48000 * block_align as u32).to_le_bytes(),
+            &block_align.to_le_bytes(),
+            &bits.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn reads_16_bit_stereo_as_mono() {
+        let data: Vec<u8> = [16384_i16, 0, -32768, -32768]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let file = riff(&[(b"fmt ", fmt(FORMAT_PCM, 2, 16)), (b"data", data)]);
+        let wav = parse(&file).unwrap();
+        assert_eq!(wav.samples, vec![0.25, -1.0]);
+        assert_eq!(wav.frame_len, None);
+    }
+
+    #[test]
+    fn reads_float_and_serum_frame_length() {
+        let data: Vec<u8> = [0.5_f32, -0.25]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let file = riff(&[
+            (b"fmt ", fmt(FORMAT_FLOAT, 1, 32)),
+            (b"clm ", b"<!>2048 10000000 wavetable".to_vec()),
+            (b"junk", vec![1, 2, 3]),
+            (b"data", data),
+        ]);
+        let wav = parse(&file).unwrap();
+        assert_eq!(wav.samples, vec![0.5, -0.25]);
+        assert_eq!(wav.frame_len, Some(2048));
+    }
+
+    #[test]
+    fn reads_24_bit() {
+        let file = riff(&[
+            (b"fmt ", fmt(FORMAT_PCM, 1, 24)),
+            (b"data", vec![0x00, 0x00, 0xC0]),
+        ]);
+        assert_eq!(parse(&file).unwrap().samples, vec![-0.5]);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert!(matches!(parse(b"RIFX"), Err(WavError::Malformed)));
+        let file = riff(&[(b"fmt ", fmt(FORMAT_PCM, 1, 12)), (b"data", vec![])]);
+        assert!(matches!(
+            parse(&file),
+            Err(WavError::UnsupportedFormat {
+                format: 1,
+                bits: 12
+            })
+        ));
+    }
+}
@@ -0,0 +1,220 @@
+use std::f32::consts::PI;
+
+/// Largest oversampling factor.
+pub const MAX_FACTOR: usize = 8;
+/// Taps in each polyphase branch, so the whole decimation filter has `TAPS * factor` taps.
+const TAPS: usize = 48;
+/// Cutoff of the decimation filter, as a fraction of the output sample rate. The filter is
+/// half way down at Nyquist and closed soon after, so the little that aliases lands just
+/// below Nyquist, above hearing at the usual sample rates.
+const CUTOFF: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// How many times faster than the sample rate the oscillators run.
+pub enum Oversampling {
+    Off,
+    X2,
+    X4,
+    X8,
+}
+impl Oversampling {
+    pub fn from_index(index: f32) -> Self {
+        match index {
+            x if x < 1.0 => Oversampling::Off,
+            x if x < 2.0 => Oversampling::X2,
+            x if x < 3.0 => Oversampling::X4,
+            _ => Oversampling::X8,
+        }
+    }
+    pub fn factor(&self) -> usize {
+        match self {
+            Oversampling::Off => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 4,
+            Oversampling::X8 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Windowed sinc lowpass for decimating by `factor`, split into `factor` polyphase branches.
+pub struct DecimationFilter {
+    factor: usize,
+    /// Branch `p` holds taps `p`, `p + factor`, `p + 2 * factor`, ... of the filter.
+    branches: [[f32; TAPS]; MAX_FACTOR],
+}
+impl DecimationFilter {
+    pub fn new(factor: usize) -> Self {
+        let factor = factor.clamp(1, MAX_FACTOR);
+        let len = TAPS * factor;
+        let cutoff = CUTOFF / factor as f32;
+        let centre = (len - 1) as f32 / 2.0;
+        let mut branches = [[0.0; TAPS]; MAX_FACTOR];
+        for n in 0..len {
+            let t = n as f32 - centre;
+            let sinc = (2.0 * PI * cutoff * t).sin() / (PI * t);
+            // Blackman window
+            let x = 2.0 * PI * n as f32 / (len - 1) as f32;
+            let window = 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos();
+            branches[n % factor][n / factor] = sinc * window;
+        }
+        // Unity gain at DC
+        let sum: f32 = branches.iter().flatten().sum();
+        branches.iter_mut().flatten().for_each(|x| *x /= sum);
+        Self { factor, branches }
+    }
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+    /// Output samples the filter delays its input by, to the nearest sample. Nothing is
+    /// delayed without oversampling, as the decimator is skipped.
+    pub fn delay(&self) -> usize {
+        if self.factor == 1 {
+            0
+        } else {
+            ((TAPS * self.factor - 1) as f32 / (2.0 * self.factor as f32)).round() as usize
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Polyphase decimator state for a single channel.
+///
+/// Each branch filters every `factor`th input sample at the output rate, so only the output
+/// samples which are kept are ever calculated.
+pub struct Decimator {
+    /// Past inputs of each branch, newest first from `position`. Every input is written
+    /// twice, `TAPS` apart, so the latest `TAPS` inputs are always contiguous.
+    history: [[f32; TAPS * 2]; MAX_FACTOR],
+    position: usize,
+}
+impl Default for Decimator {
+    fn default() -> Self {
+        Self {
+            history: [[0.0; TAPS * 2]; MAX_FACTOR],
+            position: 0,
+        }
+    }
+}
+impl Decimator {
+    /// Takes the `filter.factor()` oversampled inputs making up one output sample, oldest
+    /// first, and returns that output sample.
+    pub fn process(&mut self, filter: &DecimationFilter, input: &[f32]) -> f32 {
+        debug_assert_eq!(input.len(), filter.factor);
+        self.position = (self.position + TAPS - 1) % TAPS;
+        let position = self.position;
+        self.history
+            .iter_mut()
+            .zip(filter.branches.iter())
+            .zip(input.iter().rev())
+            .map(|((history, branch), &sample)| {
+                history[position] = sample;
+                history[position + TAPS] = sample;
+                history[position..position + TAPS]
+                    .iter()
+                    .zip(branch.iter())
+                    .map(|(x, tap)| x * tap)
+                    .sum::<f32>()
+            })
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Delays a signal which skips the decimator by the decimation filter's delay, so it stays
+/// in time with the oscillators.
+pub struct Delay {
+    buffer: [f32; TAPS],
+    position: usize,
+}
+impl Default for Delay {
+    fn default() -> Self {
+        Self {
+            buffer: [0.0; TAPS],
+            position: 0,
+        }
+    }
+}
+impl Delay {
+    /// Returns the input from `delay` samples ago, which must be less than `TAPS`.
+    pub fn process(&mut self, input: f32, delay: usize) -> f32 {
+        self.buffer[self.position] = input;
+        let output = self.buffer[(self.position + TAPS - delay) % TAPS];
+        self.position = (self.position + 1) % TAPS;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gain of decimating a sine at `frequency` cycles per oversampled sample, measured by
+    /// RMS after the filter has settled.
+    fn gain(factor: usize, frequency: f32) -> f32 {
+        let filter = DecimationFilter::new(factor);
+        let mut decimator = Decimator::default();
+        let outputs: Vec<f32> = (0..2000)
+            .map(|n| {
+                let input: Vec<f32> = (0..factor)
+                    .map(|i| (2.0 * PI * frequency * (n * factor + i) as f32).sin())
+                    .collect();
+                decimator.process(&filter, &input)
+            })
+            .collect();
+        let settled = &outputs[500..];
+        (settled.iter().map(|x| x * x).sum::<f32>() / settled.len() as f32 * 2.0).sqrt()
+    }
+
+    #[test]
+    fn dc_passes_unchanged() {
+        for factor in [2, 4, 8] {
+            let filter = DecimationFilter::new(factor);
+            let mut decimator = Decimator::default();
+            let mut output = 0.0;
+            for _ in 0..100 {
+                output = decimator.process(&filter, &vec![0.5; factor]);
+            }
+            assert!((output - 0.5).abs() < 1e-4, "{factor}x");
+        }
+    }
+
+    #[test]
+    fn delay_lines_up_with_the_filter() {
+        // Without oversampling the decimator is skipped
+        assert_eq!(DecimationFilter::new(1).delay(), 0);
+        for factor in [2, 4, 8] {
+            let filter = DecimationFilter::new(factor);
+            let mut decimator = Decimator::default();
+            let mut delay = Delay::default();
+            // An impulse at the last oversampled step, which is when the output sample is taken
+            let outputs: Vec<(f32, f32)> = (0..TAPS)
+                .map(|n| {
+                    let input = if n == 0 { 1.0 } else { 0.0 };
+                    let mut oversampled = vec![0.0; factor];
+                    oversampled[factor - 1] = input;
+                    let decimated = decimator.process(&filter, &oversampled);
+                    (decimated, delay.process(input, filter.delay()))
+                })
+                .collect();
+            let peak = (0..TAPS)
+                .max_by(|&a, &b| outputs[a].0.total_cmp(&outputs[b].0))
+                .unwrap();
+            assert_eq!(peak, filter.delay(), "{factor}x");
+            assert_eq!(outputs[peak].1, 1.0, "{factor}x");
+        }
+    }
+
+    #[test]
+    fn passes_audio_and_removes_what_would_alias() {
+        for factor in [2, 4, 8] {
+            // Frequencies relative to the output sample rate
+            let at = |frequency: f32| gain(factor, frequency / factor as f32);
+            assert!((at(0.05) - 1.0).abs() < 0.01, "{factor}x");
+            assert!((at(0.4) - 1.0).abs() < 0.02, "{factor}x");
+            // Anything above Nyquist would fold back down
+            assert!(at(0.6) < 0.001, "{factor}x");
+            assert!(at(0.9) < 0.001, "{factor}x");
+        }
+    }
+}
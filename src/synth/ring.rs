@@ -0,0 +1,79 @@
+use super::smoothing::{Smoother, SmoothingBlock};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Pair of oscillators multiplied by the ring modulator.
+pub enum RingSources {
+    OneTwo,
+    TwoThree,
+    OneThree,
+}
+impl RingSources {
+    pub fn from_index(index: f32) -> Self {
+        match index {
+            x if x < 1.0 => RingSources::OneTwo,
+            x if x < 2.0 => RingSources::TwoThree,
+            _ => RingSources::OneThree,
+        }
+    }
+    /// Indices of the two oscillators.
+    pub fn indices(&self) -> (usize, usize) {
+        match self {
+            RingSources::OneTwo => (0, 1),
+            RingSources::TwoThree => (1, 2),
+            RingSources::OneThree => (0, 2),
+        }
+    }
+}
+
+/// Controls for the dedicated ring modulator, which multiplies two oscillators' waves and
+/// mixes the product in alongside the oscillators.
+///
+/// The oscillators are multiplied before their levels are applied, so the product can be
+/// heard on its own with both oscillators turned down. Unison is normalised the same way as
+/// in the mix, but waves keep their own levels, so two saws (peaking at π/2) multiply to a
+/// product about 1.6 times as loud as two sines.
+pub struct RingModParams {
+    pub sources: RingSources,
+    pub level: f32,
+    level_smoother: Smoother,
+}
+impl RingModParams {
+    pub fn update_smoothing(&mut self, block: &SmoothingBlock) {
+        self.level_smoother.next_block(self.level, block);
+    }
+    /// Returns the ring modulator's output `index` samples into the current buffer, given
+    /// each oscillator's latest output.
+    #[inline]
+    pub fn output(&self, index: usize, outputs: &[f32; 3]) -> f32 {
+        let (a, b) = self.sources.indices();
+        outputs[a] * outputs[b] * self.level_smoother.at(index)
+    }
+}
+impl Default for RingModParams {
+    fn default() -> Self {
+        Self {
+            sources: RingSources::OneTwo,
+            level: 0.0,
+            level_smoother: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::smoothing::SmoothingParams;
+
+    #[test]
+    fn multiplies_the_chosen_oscillators() {
+        let mut ring = RingModParams {
+            sources: RingSources::TwoThree,
+            level: 0.5,
+            ..Default::default()
+        };
+        ring.update_smoothing(&SmoothingParams::default().block(64, 48000.0));
+        assert_eq!(ring.output(0, &[1.0, -0.5, 0.5]), -0.125);
+        ring.sources = RingSources::OneThree;
+        assert_eq!(ring.output(0, &[1.0, -0.5, 0.5]), 0.25);
+    }
+}
@@ -0,0 +1,157 @@
+use super::lerp;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmoothingMode {
+    /// Exponential approach, reaching ~63% of a change after the smoothing time.
+    OnePole,
+    /// Constant rate ramp, reaching the new value exactly after the smoothing time.
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// User settings shared by every `Smoother`.
+pub struct SmoothingParams {
+    pub mode: SmoothingMode,
+    /// Smoothing time in seconds. A time of 0 disables smoothing.
+    pub time: f32,
+}
+impl SmoothingParams {
+    /// Returns the settings for a buffer of `len` samples at `sample_rate`.
+    pub fn block(&self, len: usize, sample_rate: f32) -> SmoothingBlock {
+        SmoothingBlock {
+            mode: self.mode,
+            len,
+            time: self.time * sample_rate,
+        }
+    }
+}
+impl Default for SmoothingParams {
+    fn default() -> Self {
+        Self {
+            mode: SmoothingMode::OnePole,
+            time: 0.02,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Smoothing settings for a single buffer, with the smoothing time converted into samples.
+pub struct SmoothingBlock {
+    mode: SmoothingMode,
+    len: usize,
+    time: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Removes steps ("zipper noise") from a control which is only updated once per buffer.
+///
+/// The value at the end of each buffer is found using the selected `SmoothingMode`, and
+/// values inside the buffer are linearly interpolated from the previous buffer's end. This
+/// means every voice can read the same value at a given sample index without the smoother
+/// storing a whole buffer.
+pub struct Smoother {
+    start: f32,
+    end: f32,
+    /// Reciprocal of the current buffer length.
+    step: f32,
+    /// Per-sample increment of the linear ramp.
+    ramp: f32,
+    target: f32,
+    initialised: bool,
+}
+impl Smoother {
+    /// Moves the smoother towards `target` over the next buffer.
+    pub fn next_block(&mut self, target: f32, block: &SmoothingBlock) {
+        self.start = self.end;
+        self.step = 1.0 / block.len.max(1) as f32;
+
+        // Jump straight to the first value we see, otherwise every control would sweep up
+        // from 0 when the plugin is loaded.
+        if !self.initialised || block.time < 1.0 {
+            self.initialised = true;
+            self.start = target;
+            self.end = target;
+            self.target = target;
+            return;
+        }
+
+        match block.mode {
+            SmoothingMode::OnePole => {
+                let coef = (-(block.len as f32) / block.time).exp();
+                self.end = lerp(target, self.start, coef);
+            }
+            SmoothingMode::Linear => {
+                if target != self.target {
+                    self.ramp = (target - self.start) / block.time;
+                }
+                let distance = target - self.start;
+                let movement = self.ramp * block.len as f32;
+                self.end = if movement.abs() >= distance.abs() {
+                    target
+                } else {
+                    self.start + movement
+                };
+            }
+        }
+        self.target = target;
+
+        // Snap once the remaining distance is inaudible, so the one-pole doesn't crawl
+        // towards the target forever.
+        if (self.end - target).abs() <= f32::EPSILON * target.abs().max(1.0) {
+            self.end = target;
+        }
+    }
+    /// Whether the value changes during the current buffer.
+    pub fn is_moving(&self) -> bool {
+        self.start != self.end
+    }
+    /// Returns the smoothed value `index` samples into the current buffer.
+    #[inline]
+    pub fn at(&self, index: usize) -> f32 {
+        lerp(self.start, self.end, (index + 1) as f32 * self.step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(mode: SmoothingMode, time: f32) -> SmoothingBlock {
+        SmoothingParams { mode, time }.block(100, 1000.0)
+    }
+
+    #[test]
+    fn first_value_is_not_smoothed() {
+        let mut smoother = Smoother::default();
+        smoother.next_block(5.0, &block(SmoothingMode::OnePole, 1.0));
+        assert_eq!(smoother.at(0), 5.0);
+        assert_eq!(smoother.at(99), 5.0);
+    }
+
+    #[test]
+    fn linear_ramp_reaches_target_after_smoothing_time() {
+        let mut smoother = Smoother::default();
+        let block = block(SmoothingMode::Linear, 0.3);
+        smoother.next_block(0.0, &block);
+        smoother.next_block(3.0, &block);
+        assert!((smoother.at(49) - 0.5).abs() < 1e-5);
+        assert!((smoother.at(99) - 1.0).abs() < 1e-5);
+        smoother.next_block(3.0, &block);
+        smoother.next_block(3.0, &block);
+        assert_eq!(smoother.at(99), 3.0);
+        smoother.next_block(3.0, &block);
+        assert_eq!(smoother.at(0), 3.0);
+    }
+
+    #[test]
+    fn one_pole_is_continuous_between_buffers() {
+        let mut smoother = Smoother::default();
+        let block = block(SmoothingMode::OnePole, 0.1);
+        smoother.next_block(0.0, &block);
+        smoother.next_block(1.0, &block);
+        let end = smoother.at(99);
+        assert!((end - (1.0 - (-1.0_f32).exp())).abs() < 1e-5);
+        smoother.next_block(1.0, &block);
+        assert!(smoother.at(0) > end && smoother.at(0) - end < 0.01);
+    }
+}
@@ -0,0 +1,116 @@
+use super::lerp;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VelocityCurve {
+    Linear,
+    /// Soft notes are much quieter, leaving more room for dynamics at the top.
+    Exponential,
+    /// Soft notes are only a little quieter, which suits keyboards that are hard to play loud.
+    Logarithmic,
+    /// Every note plays at full velocity.
+    Fixed,
+}
+impl VelocityCurve {
+    pub fn apply(self, velocity: f32) -> f32 {
+        match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Exponential => velocity * velocity,
+            VelocityCurve::Logarithmic => velocity.sqrt(),
+            VelocityCurve::Fixed => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// How note velocity affects each part of the synth.
+///
+/// Velocities are between 0.0 and 1.0. Each amount of 0 means that part ignores velocity.
+/// The filter envelope depth has its own velocity control on the filter controller.
+pub struct VelocityParams {
+    /// How much velocity scales the output volume, after `amp_curve` is applied.
+    pub amp_amount: f32,
+    pub amp_curve: VelocityCurve,
+    /// Octaves the filter cutoff is lowered by for the softest notes. Negative values make
+    /// soft notes brighter instead.
+    pub cutoff: f32,
+    /// How much velocity shortens the envelope attacks. At 1 the hardest notes attack almost
+    /// instantly, while negative values make hard notes attack more slowly.
+    pub attack: f32,
+    /// How much velocity scales each oscillator's level.
+    pub osc_amp: [f32; 3],
+    /// How much velocity scales the PM, FM and AM each oscillator receives.
+    pub osc_mod: [f32; 3],
+}
+impl VelocityParams {
+    pub fn amp(&self, velocity: f32) -> f32 {
+        lerp(1.0, self.amp_curve.apply(velocity), self.amp_amount)
+    }
+    pub fn cutoff_mult(&self, velocity: f32) -> f32 {
+        ((velocity - 1.0) * self.cutoff).exp2()
+    }
+    /// Multiplier for the envelope attack times.
+    pub fn attack_scale(&self, velocity: f32) -> f32 {
+        (1.0 - self.attack * velocity).max(0.0)
+    }
+    pub fn osc_amp(&self, velocity: f32, oscillator: usize) -> f32 {
+        lerp(1.0, velocity, self.osc_amp[oscillator])
+    }
+    pub fn osc_mod(&self, velocity: f32, oscillator: usize) -> f32 {
+        lerp(1.0, velocity, self.osc_mod[oscillator])
+    }
+}
+impl Default for VelocityParams {
+    fn default() -> Self {
+        Self {
+            amp_amount: 1.0,
+            amp_curve: VelocityCurve::Linear,
+            cutoff: 0.0,
+            attack: 0.0,
+            osc_amp: [0.0; 3],
+            osc_mod: [0.0; 3],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_meet_at_the_ends() {
+        for curve in [
+            VelocityCurve::Linear,
+            VelocityCurve::Exponential,
+            VelocityCurve::Logarithmic,
+        ] {
+            assert_eq!(curve.apply(0.0), 0.0);
+            assert_eq!(curve.apply(1.0), 1.0);
+        }
+        assert!(VelocityCurve::Exponential.apply(0.5) < 0.5);
+        assert!(VelocityCurve::Logarithmic.apply(0.5) > 0.5);
+        assert_eq!(VelocityCurve::Fixed.apply(0.0), 1.0);
+    }
+
+    #[test]
+    fn zero_amounts_ignore_velocity() {
+        let params = VelocityParams {
+            amp_amount: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(params.amp(0.25), 1.0);
+        assert_eq!(params.cutoff_mult(0.25), 1.0);
+        assert_eq!(params.attack_scale(0.25), 1.0);
+        assert_eq!(params.osc_amp(0.25, 0), 1.0);
+        assert_eq!(params.osc_mod(0.25, 2), 1.0);
+    }
+
+    #[test]
+    fn full_velocity_keeps_the_cutoff() {
+        let params = VelocityParams {
+            cutoff: 2.0,
+            ..Default::default()
+        };
+        assert_eq!(params.cutoff_mult(1.0), 1.0);
+        assert_eq!(params.cutoff_mult(0.0), 0.25);
+    }
+}